@@ -0,0 +1,398 @@
+use crate::chunks::{Checksum, FormatVersion, InfoChunk, InfoValue};
+use crate::codec::{validate_codec_name, Codec, CodecRegistry};
+use crate::error::BdfError;
+use crate::executor::Executor;
+use crate::io::{BDFReader, BDFWriter, ReadLimits, Validation};
+use crate::registry::ChunkDecoder;
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Collects the configuration of a [`BDFWriter`] and validates it
+/// before the writer is created
+#[derive(Debug, Clone)]
+pub struct BDFWriterBuilder {
+    entry_count: Option<u64>,
+    compression_method: Option<String>,
+    codec: Option<Arc<dyn Codec>>,
+    compression_level: Option<u32>,
+    entries_per_chunk: Option<u32>,
+    bytes_per_chunk: Option<u64>,
+    threads: Option<usize>,
+    executor: Option<Arc<dyn Executor>>,
+    write_index: bool,
+    write_stats: bool,
+    utf8: bool,
+    format_version: Option<FormatVersion>,
+    checksum: Checksum,
+    info: InfoChunk,
+}
+
+/// Collects the configuration of a [`BDFReader`] and validates it
+/// before the reader is created
+#[derive(Debug, Clone, Default)]
+pub struct BDFReaderBuilder {
+    codecs: Vec<Arc<dyn Codec>>,
+    chunk_decoders: Vec<Arc<dyn ChunkDecoder>>,
+    threads: Option<usize>,
+    prefetch: Option<usize>,
+    executor: Option<Arc<dyn Executor>>,
+    limits: ReadLimits,
+    validation: Validation,
+    checksum: Checksum,
+}
+
+impl Default for BDFWriterBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BDFWriterBuilder {
+    /// Creates a builder for an uncompressed file with the default settings
+    pub fn new() -> Self {
+        Self {
+            entry_count: None,
+            compression_method: None,
+            codec: None,
+            compression_level: None,
+            entries_per_chunk: None,
+            bytes_per_chunk: None,
+            threads: None,
+            executor: None,
+            write_index: false,
+            write_stats: false,
+            utf8: false,
+            format_version: None,
+            checksum: Checksum::default(),
+            info: InfoChunk::new(),
+        }
+    }
+
+    /// Sets the total number of entries that will be written.
+    /// Without it the counts are patched in by [`BDFWriterBuilder::build_seekable`]
+    /// or written at the end of the file by [`BDFWriterBuilder::build`].
+    pub fn entry_count(mut self, entry_count: u64) -> Self {
+        self.entry_count = Some(entry_count);
+        self
+    }
+
+    /// Compresses the data chunks with the built-in codec of the given name
+    pub fn compression_method(mut self, method: &str) -> Self {
+        self.compression_method = Some(method.to_string());
+        self.codec = None;
+        self
+    }
+
+    /// Compresses the data chunks with the given codec
+    pub fn codec(mut self, codec: Arc<dyn Codec>) -> Self {
+        self.codec = Some(codec);
+        self.compression_method = None;
+        self
+    }
+
+    /// Sets the level passed to the codec
+    pub fn compression_level(mut self, level: u32) -> Self {
+        self.compression_level = Some(level);
+        self
+    }
+
+    /// Sets the maximum number of entries in a data chunk
+    pub fn entries_per_chunk(mut self, entries_per_chunk: u32) -> Self {
+        self.entries_per_chunk = Some(entries_per_chunk);
+        self
+    }
+
+    /// Starts a new data chunk when the serialized entries reach the number of bytes.
+    /// This can't be combined with a known entry count.
+    pub fn bytes_per_chunk(mut self, bytes: u64) -> Self {
+        self.bytes_per_chunk = Some(bytes);
+        self
+    }
+
+    /// Sets the number of threads compressing data chunks
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Compresses the data chunks on the executor instead of private threads
+    pub fn executor(mut self, executor: Arc<dyn Executor>) -> Self {
+        self.executor = Some(executor);
+        self
+    }
+
+    /// Enables writing the `CIDX` chunk index at the end of the file
+    pub fn write_index(mut self, write_index: bool) -> Self {
+        self.write_index = write_index;
+        self
+    }
+
+    /// Enables writing a `stat` chunk before each data chunk
+    pub fn write_stats(mut self, write_stats: bool) -> Self {
+        self.write_stats = write_stats;
+        self
+    }
+
+    /// Declares that every plaintext is valid UTF-8 and rejects entries with other plaintexts
+    pub fn utf8(mut self, utf8: bool) -> Self {
+        self.utf8 = utf8;
+        self
+    }
+
+    /// Sets the version of the format the file is written in.
+    /// Without it the file is written in version 2 if the index, stats or info are enabled.
+    pub fn format_version(mut self, version: FormatVersion) -> Self {
+        self.format_version = Some(version);
+        self
+    }
+
+    /// Sets the checksum stored at the end of every chunk
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Stores a value in the `INFO` chunk of the file
+    pub fn info<V: Into<InfoValue>>(mut self, key: &str, value: V) -> Self {
+        self.info.insert(key, value);
+        self
+    }
+
+    /// Creates the file at the given path and returns a writer for it.
+    /// The configuration is validated before the file is created.
+    pub fn create<P: AsRef<Path>>(self, path: P) -> Result<BDFWriter<File>, BdfError> {
+        self.validate()?;
+        self.seekable_writer(File::create(path)?)
+    }
+
+    /// Validates the configuration and returns a writer for the output.
+    /// If no entry count was set, the counts are written in a `META` chunk at the end.
+    pub fn build<W: Write>(self, inner: W) -> Result<BDFWriter<W>, BdfError> {
+        self.validate()?;
+        if self.entry_count.is_none() && self.format_version == Some(FormatVersion::V1) {
+            return Err(invalid("the trailing meta chunk requires format version 2"));
+        }
+        let writer = match self.entry_count {
+            Some(entry_count) => BDFWriter::new(inner, entry_count, false),
+            None => BDFWriter::new_streaming(inner, false),
+        };
+
+        self.configure(writer)
+    }
+
+    /// Validates the configuration and returns a writer for the output.
+    /// If no entry count was set, the counts at the start of the file are
+    /// overwritten when the file is finished.
+    pub fn build_seekable<W: Write + Seek>(self, inner: W) -> Result<BDFWriter<W>, BdfError> {
+        self.validate()?;
+        self.seekable_writer(inner)
+    }
+
+    /// Returns a writer for the seekable output with the validated options
+    fn seekable_writer<W: Write + Seek>(self, inner: W) -> Result<BDFWriter<W>, BdfError> {
+        let writer = match self.entry_count {
+            Some(entry_count) => BDFWriter::new(inner, entry_count, false),
+            None => BDFWriter::new_seekable(inner, false),
+        };
+
+        self.configure(writer)
+    }
+
+    /// Returns an error if the options are invalid or conflict with each other.
+    /// Everything `configure` can reject is checked here,
+    /// so nothing fails after the output was created.
+    fn validate(&self) -> Result<(), BdfError> {
+        if self.entries_per_chunk == Some(0) {
+            return Err(invalid("entries per chunk must be at least 1"));
+        }
+        if self.threads == Some(0) {
+            return Err(invalid("at least one thread is required"));
+        }
+        if self.bytes_per_chunk.is_some() && self.entry_count.is_some() {
+            return Err(invalid(
+                "bytes per chunk can't be combined with a known entry count",
+            ));
+        }
+        if let Some(bytes) = self.bytes_per_chunk {
+            if bytes == 0 || bytes > u32::MAX as u64 {
+                return Err(BdfError::InvalidConfig(format!(
+                    "bytes per chunk must be between 1 and {}",
+                    u32::MAX
+                )));
+            }
+        }
+        if let Some(codec) = &self.codec {
+            validate_codec_name(codec.name())?;
+        }
+        if let Some(method) = &self.compression_method {
+            if CodecRegistry::default().get(method).is_none() {
+                return Err(BdfError::UnsupportedCompression(method.clone()));
+            }
+        }
+        let compressed = self.codec.is_some() || self.compression_method.is_some();
+        if self.compression_level.is_some() && !compressed {
+            return Err(invalid("a compression level requires a codec"));
+        }
+        let extended = self.write_index || self.write_stats || !self.info.is_empty();
+        if self.format_version == Some(FormatVersion::V1) && extended {
+            return Err(invalid(
+                "index, info and stat chunks require format version 2",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Applies the options to the writer
+    fn configure<W: Write>(self, mut writer: BDFWriter<W>) -> Result<BDFWriter<W>, BdfError> {
+        match (self.codec, &self.compression_method) {
+            (Some(codec), _) => writer.set_codec(Some(codec))?,
+            (None, Some(method)) => writer.set_compression_method(Some(method))?,
+            (None, None) => {}
+        }
+        if let Some(level) = self.compression_level {
+            writer.set_compression_level(level);
+        }
+        if let Some(entries_per_chunk) = self.entries_per_chunk {
+            writer.set_entries_per_chunk(entries_per_chunk)?;
+        }
+        if let Some(bytes) = self.bytes_per_chunk {
+            writer.set_bytes_per_chunk(bytes)?;
+        }
+        if let Some(threads) = self.threads {
+            writer.set_threads(threads)?;
+        }
+        if let Some(executor) = self.executor {
+            writer.set_executor(executor)?;
+        }
+        writer.set_write_index(self.write_index)?;
+        writer.set_write_stats(self.write_stats)?;
+        writer.set_utf8(self.utf8)?;
+        writer.set_checksum(self.checksum)?;
+        if let Some(version) = self.format_version {
+            writer.set_format_version(version)?;
+        }
+        for (key, value) in self.info.entries {
+            writer.set_info(&key, value)?;
+        }
+
+        Ok(writer)
+    }
+}
+
+impl BDFReaderBuilder {
+    /// Creates a builder with the default settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a codec that is needed to decompress the file
+    pub fn codec(mut self, codec: Arc<dyn Codec>) -> Self {
+        self.codecs.push(codec);
+        self
+    }
+
+    /// Adds a decoder for a custom chunk type stored in the file
+    pub fn chunk_decoder(mut self, decoder: Arc<dyn ChunkDecoder>) -> Self {
+        self.chunk_decoders.push(decoder);
+        self
+    }
+
+    /// Sets the number of threads decompressing data chunks
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Sets the number of data chunks that are decompressed ahead
+    pub fn prefetch(mut self, prefetch: usize) -> Self {
+        self.prefetch = Some(prefetch);
+        self
+    }
+
+    /// Decompresses the data chunks on the executor instead of private threads
+    pub fn executor(mut self, executor: Arc<dyn Executor>) -> Self {
+        self.executor = Some(executor);
+        self
+    }
+
+    /// Sets the limits for sizes and counts read from the file
+    pub fn limits(mut self, limits: ReadLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets how strictly the chunks are checked
+    pub fn validation(mut self, validation: Validation) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    /// Sets the checksum the chunks of the file are expected to have
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Opens the file at the given path and returns a reader for it.
+    /// The configuration is validated before the file is opened.
+    pub fn open<P: AsRef<Path>>(self, path: P) -> Result<BDFReader<File>, BdfError> {
+        self.validate()?;
+        self.configure(BDFReader::new_seekable(File::open(path)?))
+    }
+
+    /// Validates the configuration and returns a reader for the input
+    pub fn build<R: Read>(self, inner: R) -> Result<BDFReader<R>, BdfError> {
+        self.validate()?;
+        self.configure(BDFReader::new(inner))
+    }
+
+    /// Validates the configuration and returns a reader for the seekable input
+    /// that goes back to the first data chunk after hash lookups
+    pub fn build_seekable<R: Read + Seek>(self, inner: R) -> Result<BDFReader<R>, BdfError> {
+        self.validate()?;
+        self.configure(BDFReader::new_seekable(inner))
+    }
+
+    /// Applies the options to the reader
+    fn configure<R: Read>(self, mut reader: BDFReader<R>) -> Result<BDFReader<R>, BdfError> {
+        for codec in self.codecs {
+            reader.register_codec(codec)?;
+        }
+        for decoder in self.chunk_decoders {
+            reader.register_chunk_decoder(decoder)?;
+        }
+        if let Some(threads) = self.threads {
+            reader.set_threads(threads)?;
+        }
+        if let Some(prefetch) = self.prefetch {
+            reader.set_prefetch(prefetch)?;
+        }
+        if let Some(executor) = self.executor {
+            reader.set_executor(executor)?;
+        }
+        reader.set_limits(self.limits);
+        reader.set_validation(self.validation);
+        reader.set_checksum(self.checksum);
+
+        Ok(reader)
+    }
+
+    /// Returns an error if the options are invalid or conflict with each other
+    fn validate(&self) -> Result<(), BdfError> {
+        if self.threads == Some(0) {
+            return Err(invalid("at least one thread is required"));
+        }
+        if self.prefetch == Some(0) {
+            return Err(invalid("at least one chunk has to be prefetched"));
+        }
+
+        Ok(())
+    }
+}
+
+fn invalid(reason: &str) -> BdfError {
+    BdfError::InvalidConfig(reason.to_string())
+}
@@ -1,44 +1,132 @@
 use byteorder::{BigEndian, ByteOrder};
 use crc::crc32;
-use std::collections::HashMap;
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryFrom;
-use std::io::Read;
-use std::io::{Error, ErrorKind};
-use xz2::read::{XzDecoder, XzEncoder};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::ops::{Range, RangeInclusive};
+use std::str::Utf8Error;
+
+use crate::codec::Codec;
+use crate::error::BdfError;
+use crate::verify::Problem;
 
 pub const LZMA: &str = "lzma";
 
+/// The header of version 1 files
 pub const BDF_HDR: &[u8; 11] = b"BDF\x01RAINBOW";
+/// The magic bytes every file starts with
+pub const BDF_MAGIC: &[u8; 3] = b"BDF";
+/// The tag following the version in the header
+pub const BDF_TAG: &[u8; 7] = b"RAINBOW";
+/// The size of the file header
+pub const HEADER_SIZE: usize = 11;
 pub const NULL_BYTES: &[u8; 4] = &[0u8; 4];
 pub const META_CHUNK_NAME: &str = "META";
 pub const HTBL_CHUNK_NAME: &str = "HTBL";
 pub const DTBL_CHUNK_NAME: &str = "DTBL";
+pub const CIDX_CHUNK_NAME: &str = "CIDX";
+pub const CFTR_CHUNK_NAME: &str = "CFTR";
+pub const SORT_CHUNK_NAME: &str = "SORT";
+pub const BLOM_CHUNK_NAME: &str = "BLOM";
+pub const INFO_CHUNK_NAME: &str = "INFO";
+pub const STAT_CHUNK_NAME: &str = "stat";
+
+/// The entry count stored in the first `META` chunk of a file that was written
+/// without knowing the number of entries. The real counts are stored in a trailing `META` chunk.
+pub const UNKNOWN_ENTRY_COUNT: u64 = u64::MAX;
+/// The chunk count stored together with [`UNKNOWN_ENTRY_COUNT`]
+pub const UNKNOWN_CHUNK_COUNT: u32 = u32::MAX;
+
+/// The bit of the `META` flags declaring that every plaintext in the file is valid UTF-8
+pub const META_FLAG_UTF8: u32 = 1;
+/// The bit of the `META` flags declaring that an `INFO` chunk follows the lookup table
+pub const META_FLAG_INFO: u32 = 2;
+
+/// The maximum number of hash functions of a bloom filter in a `BLOM` chunk
+pub const MAX_BLOOM_HASH_COUNT: u32 = 64;
+
+/// The revision of the file format stored in the header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[non_exhaustive]
+pub enum FormatVersion {
+    /// The original format that every reader supports
+    #[default]
+    V1,
+    /// The format that extensions old readers can't ignore are added to.
+    /// Chunk lengths are stored as u64 instead of u32.
+    V2,
+}
+
+/// The header at the start of every file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileHeader {
+    pub magic: [u8; 3],
+    pub version: u8,
+    pub tag: [u8; 7],
+}
 
+/// The checksum stored at the end of every chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum Checksum {
+    /// crc32 (IEEE) of the uncompressed data of the chunk.
+    /// It is the only checksum the format defines so far.
+    #[default]
+    Crc32,
+}
+
+/// The four character code identifying the type of a chunk.
+/// As in PNG, a lowercase first letter marks an ancillary chunk that readers
+/// which don't know the type can skip. Chunks with an uppercase first letter
+/// are critical and readers have to fail if they don't know them.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChunkId([u8; 4]);
+
+/// A type of chunk that can be written to a file.
+/// Custom chunk types are read back with a [`crate::registry::ChunkDecoder`].
+pub trait Chunk: Debug + Send + Sync + 'static {
+    /// Returns the type of the chunk
+    fn id(&self) -> ChunkId;
+
+    /// Serializes the data of the chunk without the length, name and crc sum
+    fn serialize_data(&self) -> Vec<u8>;
+
+    /// Returns the chunk as [`Any`] so that decoded chunks can be downcast to their type
+    fn as_any(&self) -> &dyn Any;
+}
 
 #[derive(Debug, Clone)]
 pub struct GenericChunk {
-    pub length: u32,
-    pub(crate) name: String,
+    pub length: u64,
+    pub(crate) id: ChunkId,
     pub data: Vec<u8>,
     pub crc: u32,
+    /// The position of the chunk in the file, used for error reporting
+    pub(crate) index: u64,
+    /// The maximum length of a data row in the chunk
+    pub(crate) max_entry_size: u32,
 }
 
-
 #[derive(Debug, Clone)]
 pub struct MetaChunk {
     pub chunk_count: u32,
     pub entries_per_chunk: u32,
     pub entry_count: u64,
     pub compression_method: Option<String>,
+    /// True if the file guarantees that every plaintext is valid UTF-8
+    pub utf8: bool,
+    /// True if an `INFO` chunk follows the lookup table
+    pub info: bool,
 }
 
-
 #[derive(Debug, Clone)]
 pub struct HashLookupTable {
     pub entries: HashMap<u32, HashEntry>,
+    /// The ids of the entries by the name of their hash function
+    ids: HashMap<String, u32>,
 }
 
-
 #[derive(Debug, Clone)]
 pub struct HashEntry {
     pub(crate) id: u32,
@@ -46,161 +134,620 @@ pub struct HashEntry {
     name: String,
 }
 
-
 #[derive(Debug, Clone)]
 pub struct DataEntry {
-    pub plain: String,
-    hashes: HashMap<String, Vec<u8>>,
+    /// The plaintext the hash values were computed from.
+    /// It usually is UTF-8 but can contain any bytes.
+    pub plain: Vec<u8>,
+    /// The hash values of all hash functions stored back to back
+    values: Vec<u8>,
+    /// The id of every hash function and the range of its value, sorted by the id
+    hashes: Vec<(u32, Range<usize>)>,
+}
+
+/// A data row borrowed from the data of a decompressed `DTBL` chunk.
+/// Its hash values are looked up by the id of their hash function
+/// without copying the row.
+#[derive(Debug, Clone, Copy)]
+pub struct DataEntryRef<'a> {
+    plain: &'a [u8],
+    /// The serialized ids and values of the hashes
+    hashes: &'a [u8],
+    lookup_table: &'a HashLookupTable,
+}
+
+/// An iterator over the rows of a data chunk returned by [`GenericChunk::entry_refs`].
+/// It stops after the first row that can't be decoded.
+#[derive(Debug, Clone)]
+pub struct EntryRefs<'a> {
+    data: &'a [u8],
+    position: usize,
+    lookup_table: &'a HashLookupTable,
+    max_entry_size: u32,
+    failed: bool,
+}
+
+/// An iterator over the hash function ids and hash values of a [`DataEntryRef`]
+#[derive(Debug, Clone)]
+pub struct HashValues<'a> {
+    hashes: &'a [u8],
+    position: usize,
+    lookup_table: &'a HashLookupTable,
+}
+
+/// The index of all data chunks stored in the `CIDX` chunk
+#[derive(Debug, Clone, Default)]
+pub struct ChunkIndex {
+    pub entries: Vec<ChunkIndexEntry>,
+}
+
+/// The position and size of a single data chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkIndexEntry {
+    /// The byte offset of the chunk from the start of the file
+    pub offset: u64,
+    /// The length of the (compressed) chunk data
+    pub length: u64,
+    pub entry_count: u32,
+}
+
+/// The hash function the data entries are sorted by and the
+/// range of its values in each data chunk, stored in the `SORT` chunk
+#[derive(Debug, Clone)]
+pub struct SortIndex {
+    pub hash_id: u32,
+    pub ranges: Vec<KeyRange>,
+}
+
+/// The smallest and largest hash value in a data chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRange {
+    pub chunk_number: u64,
+    pub min: Vec<u8>,
+    pub max: Vec<u8>,
+}
+
+/// Statistics about the entries of the following data chunk.
+/// They are stored uncompressed in the `stat` chunk, so readers can skip
+/// data chunks that can't contain what they are looking for without decompressing them.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ChunkStats {
+    pub entry_count: u32,
+    /// The length of the data of the chunk before it was compressed
+    pub uncompressed_size: u64,
+    pub min_plain_length: u32,
+    pub max_plain_length: u32,
+    /// The smallest and largest value of every hash function, sorted by the hash id
+    pub hash_ranges: Vec<HashRange>,
+}
+
+/// The smallest and largest value of one hash function
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashRange {
+    pub hash_id: u32,
+    pub min: Vec<u8>,
+    pub max: Vec<u8>,
+}
+
+/// A bloom filter over the values of one hash function in the following data chunk.
+/// It is stored in the `BLOM` chunk.
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    pub hash_id: u32,
+    hash_count: u32,
+    bits: Vec<u8>,
+}
+
+/// The footer chunk pointing to the `CIDX` chunk
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkIndexFooter {
+    pub index_offset: u64,
+}
+
+/// Free-form information about the file like its source, the tool that
+/// generated it or a description, stored as key/value pairs sorted by key
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InfoChunk {
+    pub entries: BTreeMap<String, InfoValue>,
+}
+
+/// A typed value of the `INFO` chunk.
+/// Timestamps are stored as integers counting the seconds since the unix epoch.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum InfoValue {
+    Text(String),
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    Bytes(Vec<u8>),
+}
+
+impl Checksum {
+    /// Computes the checksum of the data
+    pub fn compute(self, data: &[u8]) -> u32 {
+        match self {
+            Checksum::Crc32 => crc32::checksum_ieee(data),
+        }
+    }
+}
+
+impl FormatVersion {
+    /// Returns the version for the number stored in the header
+    pub fn from_number(number: u8) -> Option<Self> {
+        match number {
+            1 => Some(FormatVersion::V1),
+            2 => Some(FormatVersion::V2),
+            _ => None,
+        }
+    }
+
+    /// Returns the number stored in the header
+    pub fn number(self) -> u8 {
+        match self {
+            FormatVersion::V1 => 1,
+            FormatVersion::V2 => 2,
+        }
+    }
+
+    /// Returns the size of the length field at the start of every chunk
+    pub fn length_size(self) -> usize {
+        match self {
+            FormatVersion::V1 => 4,
+            FormatVersion::V2 => 8,
+        }
+    }
+
+    /// Returns the number of bytes a chunk takes up in addition to its data
+    pub fn chunk_overhead(self) -> u64 {
+        self.length_size() as u64 + 8
+    }
+
+    /// Returns the largest length the data of a chunk can have
+    pub fn max_chunk_length(self) -> u64 {
+        match self {
+            FormatVersion::V1 => u32::MAX as u64,
+            FormatVersion::V2 => u64::MAX,
+        }
+    }
+
+    /// Returns the size of the `CFTR` chunk that ends files containing a chunk index
+    pub fn footer_size(self) -> u64 {
+        self.chunk_overhead() + 8
+    }
+}
+
+impl FileHeader {
+    /// Creates the header of a file with the given version
+    pub fn new(version: FormatVersion) -> Self {
+        Self {
+            magic: *BDF_MAGIC,
+            version: version.number(),
+            tag: *BDF_TAG,
+        }
+    }
+
+    /// Splits the raw header into its fields without validating them
+    pub fn parse(data: &[u8; HEADER_SIZE]) -> Self {
+        let mut header = Self::new(FormatVersion::V1);
+        header.magic.copy_from_slice(&data[0..3]);
+        header.version = data[3];
+        header.tag.copy_from_slice(&data[4..11]);
+
+        header
+    }
+
+    /// Returns the format version of the file.
+    /// Returns an error if the header isn't a BDF header or the version is unknown.
+    pub fn format_version(&self) -> Result<FormatVersion, BdfError> {
+        if self.magic != *BDF_MAGIC || self.tag != *BDF_TAG {
+            return Err(BdfError::InvalidHeader);
+        }
+
+        FormatVersion::from_number(self.version).ok_or(BdfError::UnsupportedVersion(self.version))
+    }
+
+    /// Serializes the header into bytes
+    pub fn serialize(&self) -> [u8; HEADER_SIZE] {
+        let mut data = [0u8; HEADER_SIZE];
+        data[0..3].copy_from_slice(&self.magic);
+        data[3] = self.version;
+        data[4..11].copy_from_slice(&self.tag);
+
+        data
+    }
+}
+
+impl ChunkId {
+    pub const META: ChunkId = ChunkId(*b"META");
+    pub const HTBL: ChunkId = ChunkId(*b"HTBL");
+    pub const DTBL: ChunkId = ChunkId(*b"DTBL");
+    pub const CIDX: ChunkId = ChunkId(*b"CIDX");
+    pub const CFTR: ChunkId = ChunkId(*b"CFTR");
+    pub const SORT: ChunkId = ChunkId(*b"SORT");
+    pub const BLOM: ChunkId = ChunkId(*b"BLOM");
+    pub const INFO: ChunkId = ChunkId(*b"INFO");
+    pub const STAT: ChunkId = ChunkId(*b"stat");
+
+    /// The types of the chunks defined by the format
+    pub const BUILTIN: [ChunkId; 9] = [
+        ChunkId::META,
+        ChunkId::HTBL,
+        ChunkId::DTBL,
+        ChunkId::CIDX,
+        ChunkId::CFTR,
+        ChunkId::SORT,
+        ChunkId::BLOM,
+        ChunkId::INFO,
+        ChunkId::STAT,
+    ];
+
+    /// Creates an id from its four bytes
+    pub const fn new(bytes: [u8; 4]) -> Self {
+        ChunkId(bytes)
+    }
+
+    /// Returns the four bytes of the id
+    pub fn as_bytes(&self) -> &[u8; 4] {
+        &self.0
+    }
+
+    /// Returns true if the id consists of four ASCII letters
+    pub fn is_valid(self) -> bool {
+        self.0.iter().all(u8::is_ascii_alphabetic)
+    }
+
+    /// Returns true if readers that don't know the type can skip the chunk
+    pub fn is_ancillary(self) -> bool {
+        self.0[0] & 0x20 != 0
+    }
+
+    /// Returns true if readers that don't know the type have to fail
+    pub fn is_critical(self) -> bool {
+        !self.is_ancillary()
+    }
+
+    /// Returns true if the type is defined by the format
+    pub fn is_builtin(self) -> bool {
+        ChunkId::BUILTIN.contains(&self)
+    }
+}
+
+impl TryFrom<&str> for ChunkId {
+    type Error = BdfError;
+
+    /// Parses an id of exactly four ASCII letters
+    fn try_from(name: &str) -> Result<ChunkId, BdfError> {
+        let bytes = <[u8; 4]>::try_from(name.as_bytes())
+            .map_err(|_| BdfError::InvalidChunkId(name.to_string()))?;
+        let id = ChunkId(bytes);
+        if !id.is_valid() {
+            return Err(BdfError::InvalidChunkId(name.to_string()));
+        }
+
+        Ok(id)
+    }
+}
+
+impl Display for ChunkId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.0))
+    }
+}
+
+impl Debug for ChunkId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "ChunkId({:?})", String::from_utf8_lossy(&self.0))
+    }
 }
 
 impl GenericChunk {
-    /// Serializes the chunk to a vector of bytes
-    pub fn serialize(&mut self) -> Vec<u8> {
+    /// Creates a new chunk with the given type and data
+    pub fn new(id: ChunkId, data: Vec<u8>) -> Self {
+        let crc_sum = crc32::checksum_ieee(data.as_slice());
+
+        GenericChunk {
+            length: data.len() as u64,
+            id,
+            data,
+            crc: crc_sum,
+            index: 0,
+            max_entry_size: u32::MAX,
+        }
+    }
+
+    /// Returns the type of the chunk
+    pub fn id(&self) -> ChunkId {
+        self.id
+    }
+
+    /// Serializes the chunk to a vector of bytes in the layout of the format version.
+    /// Fails if the length doesn't fit into the length field of the version.
+    pub fn serialize(&mut self, version: FormatVersion) -> Result<Vec<u8>, BdfError> {
+        let limit = version.max_chunk_length();
+        if self.length > limit {
+            return Err(BdfError::TooLarge {
+                what: "chunk",
+                size: self.length,
+                limit,
+            });
+        }
         let mut serialized: Vec<u8> = Vec::new();
-        let mut length_raw = [0u8; 4];
-        BigEndian::write_u32(&mut length_raw, self.length);
-        serialized.append(&mut length_raw.to_vec());
-        let name_raw = self.name.as_bytes();
-        serialized.append(&mut name_raw.to_vec());
+        match version {
+            FormatVersion::V1 => serialized.extend_from_slice(&(self.length as u32).to_be_bytes()),
+            FormatVersion::V2 => serialized.extend_from_slice(&self.length.to_be_bytes()),
+        }
+        serialized.extend_from_slice(self.id.as_bytes());
         serialized.append(&mut self.data);
         let mut crc_raw = [0u8; 4];
         BigEndian::write_u32(&mut crc_raw, self.crc);
         serialized.append(&mut crc_raw.to_vec());
 
-        serialized
+        Ok(serialized)
     }
 
     /// Returns the data entries of the chunk
     pub fn data_entries(
         &mut self,
         lookup_table: &HashLookupTable,
-    ) -> Result<Vec<DataEntry>, Error> {
-        if self.name == HTBL_CHUNK_NAME.to_string() {
-            return Err(Error::new(ErrorKind::Other, "this is not a data chunk"));
+    ) -> Result<Vec<DataEntry>, BdfError> {
+        self.entry_refs(lookup_table)?
+            .map(|entry| entry.map(|entry| entry.to_data_entry()))
+            .collect()
+    }
+
+    /// Returns an iterator over the rows of the chunk that borrows them
+    /// from the chunk data instead of decoding them into [`DataEntry`]s
+    pub fn entry_refs<'a>(
+        &'a self,
+        lookup_table: &'a HashLookupTable,
+    ) -> Result<EntryRefs<'a>, BdfError> {
+        if self.id != ChunkId::DTBL {
+            return Err(BdfError::WrongChunkType {
+                expected: DTBL_CHUNK_NAME.to_string(),
+                found: self.id.to_string(),
+            });
         }
-        let mut entries: Vec<DataEntry> = Vec::new();
-        let mut position = 0;
 
-        while self.data.len() > (position + 8) {
-            let entry_length_raw = &self.data[position..position + 4];
-            position += 4;
-            let entry_length = BigEndian::read_u32(entry_length_raw);
-            let entry_end = position + entry_length as usize;
-            let pw_length_raw = &self.data[position..position + 4];
-            position += 4;
-            let pw_length = BigEndian::read_u32(pw_length_raw);
-            let pw_plain_raw = &self.data[position..position + pw_length as usize];
-            position += pw_length as usize;
-
-            let pw_plain = String::from_utf8(pw_plain_raw.to_vec())
-                .map_err(|err| {
-                    format!(
-                        "failed to parse plain password string ({}-{}): {:?}",
-                        position,
-                        position + pw_length as usize,
-                        err
-                    )
-                })
-                .unwrap();
-            let mut hash_values: HashMap<String, Vec<u8>> = HashMap::new();
-            while position < entry_end {
-                let entry_id_raw = &self.data[position..position + 4];
-                position += 4;
-                let entry_id = BigEndian::read_u32(entry_id_raw);
-
-                if let Some(hash_entry) = lookup_table.entries.get(&entry_id) {
-                    let hash = &self.data[position..position + hash_entry.output_length as usize];
-                    position += hash_entry.output_length as usize;
-                    hash_values.insert(hash_entry.name.clone(), hash.to_vec());
-                }
+        Ok(EntryRefs::new(self, lookup_table))
+    }
+
+    /// Returns the data entries that can be decoded before the first incomplete
+    /// or damaged row. It is used to salvage the rows of a truncated chunk.
+    pub(crate) fn complete_entries(&self, lookup_table: &HashLookupTable) -> Vec<DataEntry> {
+        EntryRefs::new(self, lookup_table)
+            .map_while(Result::ok)
+            .map(|entry| entry.to_data_entry())
+            .collect()
+    }
+
+    /// Returns the data entries whose value for the hash function with the given id
+    /// is one of the target hashes together with their position in the chunk.
+    /// Only the matching entries are decoded.
+    pub fn find_entries(
+        &self,
+        lookup_table: &HashLookupTable,
+        hash_id: u32,
+        targets: &HashSet<Vec<u8>>,
+    ) -> Result<Vec<(u32, DataEntry)>, BdfError> {
+        let mut found = Vec::new();
+
+        for (entry_number, entry) in self.entry_refs(lookup_table)?.enumerate() {
+            let entry = entry?;
+            if entry
+                .hash_value(hash_id)
+                .is_some_and(|hash| targets.contains(hash))
+            {
+                found.push((entry_number as u32, entry.to_data_entry()));
             }
-            entries.push(DataEntry {
-                plain: pw_plain,
-                hashes: hash_values,
-            })
         }
 
-        Ok(entries)
+        Ok(found)
     }
 
     /// Constructs the chunk from a Vec of Data entries and a hash lookup table
     pub fn from_data_entries(
-        entries: &Vec<DataEntry>,
+        entries: &[DataEntry],
         lookup_table: &HashLookupTable,
     ) -> GenericChunk {
         let mut serialized_data: Vec<u8> = Vec::new();
 
         entries.iter().for_each(|entry| {
-            serialized_data.append(&mut entry.serialize(&lookup_table));
+            serialized_data.append(&mut entry.serialize(lookup_table));
         });
-        let crc_sum = crc32::checksum_ieee(serialized_data.as_slice());
 
-        GenericChunk {
-            length: serialized_data.len() as u32,
-            name: DTBL_CHUNK_NAME.to_string(),
-            data: serialized_data,
-            crc: crc_sum,
+        GenericChunk::new(ChunkId::DTBL, serialized_data)
+    }
+
+    /// Returns the number of data entries of a decompressed data chunk
+    /// without decoding them
+    pub fn entry_count(&self) -> Result<u32, BdfError> {
+        let mut count = 0;
+        let mut position = 0;
+
+        while position < self.data.len() {
+            let entry_length = read_u32(&self.data, &mut position)?;
+            read_slice(&self.data, &mut position, entry_length as usize)?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Checks that the rows of a decompressed data chunk only reference hash functions
+    /// of the lookup table and that their hash values have the output lengths stored in it.
+    /// Returns the number of rows together with the problems found.
+    pub(crate) fn verify_entries(
+        &self,
+        lookup_table: &HashLookupTable,
+        utf8: bool,
+    ) -> Result<(u32, Vec<Problem>), BdfError> {
+        let mut problems = Vec::new();
+        let mut position = 0;
+        let mut entry_number = 0;
+
+        while position < self.data.len() {
+            let entry_length = read_u32(&self.data, &mut position)?;
+            let row = read_slice(&self.data, &mut position, entry_length as usize)?;
+            if let Err(problem) = verify_row(row, lookup_table, utf8, self.index, entry_number)? {
+                problems.push(problem);
+            }
+            entry_number += 1;
+        }
+
+        Ok((entry_number, problems))
+    }
+
+    /// Returns an error if the crc sum doesn't match the data.
+    /// Compressed data chunks have to be decompressed first.
+    pub fn verify_crc(&self) -> Result<(), BdfError> {
+        let crc = crc32::checksum_ieee(&self.data);
+        if crc != self.crc {
+            return Err(BdfError::CrcMismatch {
+                chunk_index: self.index,
+                expected: self.crc,
+                actual: crc,
+            });
         }
+
+        Ok(())
     }
 
-    /// Compresses the data of the chunk using lzma with a level of 6
-    pub fn compress(&mut self, level: u32) -> Result<(), Error> {
-        let data = self.data.as_slice();
-        let mut compressor = XzEncoder::new(data, level);
-        let mut compressed: Vec<u8> = Vec::new();
-        compressor.read_to_end(&mut compressed)?;
-        self.length = compressed.len() as u32;
+    /// Compresses the data of the chunk with the codec
+    pub fn compress(&mut self, codec: &dyn Codec, level: u32) -> Result<(), BdfError> {
+        let compressed = codec.compress(&self.data, level)?;
+        self.length = compressed.len() as u64;
         self.data = compressed;
 
         Ok(())
     }
 
-    /// Decompresses the data of the chunk with lzma
-    pub fn decompress(&mut self) -> Result<(), Error> {
-        let data = self.data.as_slice();
-        let mut decompressor = XzDecoder::new(data);
-        let mut decompressed: Vec<u8> = Vec::new();
-        decompressor.read_to_end(&mut decompressed)?;
+    /// Decompresses the data of the chunk with the codec and verifies its crc sum
+    pub fn decompress(&mut self, codec: &dyn Codec) -> Result<(), BdfError> {
+        self.decompress_limited(codec, u64::MAX)
+    }
+
+    /// Decompresses the data of the chunk with the codec and verifies its crc sum.
+    /// Returns an error if the decompressed data is longer than `max_size` bytes.
+    pub fn decompress_limited(&mut self, codec: &dyn Codec, max_size: u64) -> Result<(), BdfError> {
+        let decompressed = codec.decompress_limited(&self.data, max_size)?;
         let crc = crc32::checksum_ieee(decompressed.as_slice());
 
         if crc != self.crc {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                "the crc doesn't match the decrypted data",
-            ));
+            return Err(BdfError::CrcMismatch {
+                chunk_index: self.index,
+                expected: self.crc,
+                actual: crc,
+            });
         }
-        self.length = decompressed.len() as u32;
+        self.length = decompressed.len() as u64;
         self.data = decompressed;
 
         Ok(())
     }
 }
 
-impl From<&MetaChunk> for GenericChunk {
-    fn from(chunk: &MetaChunk) -> GenericChunk {
-        let serialized_data = chunk.serialize();
-        let crc_sum = crc32::checksum_ieee(serialized_data.as_slice());
+impl<C: Chunk + ?Sized> From<&C> for GenericChunk {
+    fn from(chunk: &C) -> GenericChunk {
+        GenericChunk::new(chunk.id(), chunk.serialize_data())
+    }
+}
 
-        GenericChunk {
-            length: serialized_data.len() as u32,
-            name: META_CHUNK_NAME.to_string(),
-            data: serialized_data,
-            crc: crc_sum,
-        }
+impl Chunk for MetaChunk {
+    fn id(&self) -> ChunkId {
+        ChunkId::META
+    }
+
+    fn serialize_data(&self) -> Vec<u8> {
+        self.serialize()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
     }
 }
 
-impl From<&HashLookupTable> for GenericChunk {
-    fn from(chunk: &HashLookupTable) -> GenericChunk {
-        let serialized_data = chunk.serialize();
-        let crc_sum = crc32::checksum_ieee(serialized_data.as_slice());
+impl Chunk for HashLookupTable {
+    fn id(&self) -> ChunkId {
+        ChunkId::HTBL
+    }
 
-        GenericChunk {
-            length: serialized_data.len() as u32,
-            name: HTBL_CHUNK_NAME.to_string(),
-            data: serialized_data,
-            crc: crc_sum,
-        }
+    fn serialize_data(&self) -> Vec<u8> {
+        self.serialize()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Chunk for SortIndex {
+    fn id(&self) -> ChunkId {
+        ChunkId::SORT
+    }
+
+    fn serialize_data(&self) -> Vec<u8> {
+        self.serialize()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Chunk for BloomFilter {
+    fn id(&self) -> ChunkId {
+        ChunkId::BLOM
+    }
+
+    fn serialize_data(&self) -> Vec<u8> {
+        self.serialize()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Chunk for InfoChunk {
+    fn id(&self) -> ChunkId {
+        ChunkId::INFO
+    }
+
+    fn serialize_data(&self) -> Vec<u8> {
+        self.serialize()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Chunk for ChunkStats {
+    fn id(&self) -> ChunkId {
+        ChunkId::STAT
+    }
+
+    fn serialize_data(&self) -> Vec<u8> {
+        self.serialize()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Chunk for ChunkIndexFooter {
+    fn id(&self) -> ChunkId {
+        ChunkId::CFTR
+    }
+
+    fn serialize_data(&self) -> Vec<u8> {
+        self.index_offset.to_be_bytes().to_vec()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
     }
 }
 
@@ -212,13 +759,38 @@ impl MetaChunk {
         } else {
             None
         };
-        let chunk_count = (entry_count as f64 / entries_per_chunk as f64).ceil() as u32;
+        let chunk_count = chunk_count_for(entry_count, entries_per_chunk);
 
         Self {
             chunk_count,
             entry_count,
             entries_per_chunk,
             compression_method,
+            utf8: false,
+            info: false,
+        }
+    }
+
+    /// Creates a meta chunk for a file whose number of entries isn't known yet
+    pub fn with_unknown_counts(entries_per_chunk: u32, compress: bool) -> Self {
+        Self {
+            chunk_count: UNKNOWN_CHUNK_COUNT,
+            entry_count: UNKNOWN_ENTRY_COUNT,
+            ..Self::new(0, entries_per_chunk, compress)
+        }
+    }
+
+    /// Returns false if the counts of the chunk are placeholders
+    /// for the counts in a trailing `META` chunk
+    pub fn counts_known(&self) -> bool {
+        self.entry_count != UNKNOWN_ENTRY_COUNT
+    }
+
+    /// Changes the number of entries per chunk and updates the chunk count
+    pub fn set_entries_per_chunk(&mut self, entries_per_chunk: u32) {
+        self.entries_per_chunk = entries_per_chunk;
+        if self.counts_known() {
+            self.chunk_count = chunk_count_for(self.entry_count, entries_per_chunk);
         }
     }
 
@@ -234,30 +806,39 @@ impl MetaChunk {
         let mut total_entries_raw = [0u8; 8];
         BigEndian::write_u64(&mut total_entries_raw, self.entry_count);
         serialized_data.append(&mut total_entries_raw.to_vec());
-        let mut compression_method = self.compression_method.clone();
-
-        if let Some(method) = &mut compression_method {
+        if let Some(method) = &self.compression_method {
             serialized_data.append(&mut method.clone().into_bytes());
         } else {
             serialized_data.append(&mut vec![0, 0, 0, 0]);
         }
+        // the flags are optional so that files without any stay readable by older versions
+        let mut flags = 0;
+        if self.utf8 {
+            flags |= META_FLAG_UTF8;
+        }
+        if self.info {
+            flags |= META_FLAG_INFO;
+        }
+        if flags != 0 {
+            serialized_data.extend_from_slice(&flags.to_be_bytes());
+        }
 
         serialized_data
     }
 }
 
 impl TryFrom<GenericChunk> for MetaChunk {
-    type Error = Error;
+    type Error = BdfError;
 
-    fn try_from(chunk: GenericChunk) -> Result<MetaChunk, Error> {
-        if &chunk.name != META_CHUNK_NAME {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                "chunk name doesn't match",
-            ));
+    fn try_from(chunk: GenericChunk) -> Result<MetaChunk, BdfError> {
+        if chunk.id != ChunkId::META {
+            return Err(BdfError::WrongChunkType {
+                expected: META_CHUNK_NAME.to_string(),
+                found: chunk.id.to_string(),
+            });
         }
         if chunk.data.len() < 20 {
-            return Err(Error::new(ErrorKind::InvalidData, "invalid chunk data"));
+            return Err(BdfError::Truncated);
         }
         let chunk_count_raw = &chunk.data[0..4];
         let entries_per_chunk = &chunk.data[4..8];
@@ -266,41 +847,71 @@ impl TryFrom<GenericChunk> for MetaChunk {
         let chunk_count = BigEndian::read_u32(chunk_count_raw);
         let entries_per_chunk = BigEndian::read_u32(entries_per_chunk);
         let entry_count = BigEndian::read_u64(total_number_of_entries);
-        let compression_method = if &compression_method_raw != NULL_BYTES {
-            Some(
-                String::from_utf8(compression_method_raw)
-                    .expect("Failed to parse compression method name!"),
-            )
+        let compression_method = if compression_method_raw != NULL_BYTES {
+            Some(String::from_utf8(compression_method_raw)?)
         } else {
             None
         };
+        let flags = match chunk.data.get(20..24) {
+            Some(flags_raw) => BigEndian::read_u32(flags_raw),
+            None => 0,
+        };
 
         Ok(MetaChunk {
             chunk_count,
             entries_per_chunk,
             entry_count,
             compression_method,
+            utf8: flags & META_FLAG_UTF8 != 0,
+            info: flags & META_FLAG_INFO != 0,
         })
     }
 }
 
 impl HashLookupTable {
-
     /// Creates a new hash lookup table
     pub fn new(entries: HashMap<u32, HashEntry>) -> Self {
-        Self { entries }
+        let ids = entries
+            .iter()
+            .map(|(id, entry)| (entry.name.clone(), *id))
+            .collect();
+
+        Self { entries, ids }
+    }
+
+    /// Adds the entry with the given id and indexes it by its name
+    pub fn insert(&mut self, id: u32, mut entry: HashEntry) {
+        entry.id = id;
+        self.ids.insert(entry.name.clone(), id);
+        self.entries.insert(id, entry);
+    }
+
+    /// Returns the id of the hash function with the given name
+    pub fn get_id(&self, name: &str) -> Option<u32> {
+        match self.ids.get(name) {
+            Some(id) if self.entries.get(id).is_some_and(|entry| entry.name == name) => Some(*id),
+            // entries inserted into the map directly aren't indexed
+            _ => self
+                .entries
+                .iter()
+                .find(|(_, entry)| entry.name == name)
+                .map(|(id, _)| *id),
+        }
     }
 
     /// Returns an entry by the name of the hash function
-    pub fn get_entry(&self, name: &String) -> Option<(&u32, &HashEntry)> {
-        self.entries.iter().find(|(_, entry)| entry.name == *name)
+    pub fn get_entry(&self, name: &str) -> Option<(&u32, &HashEntry)> {
+        self.entries.get_key_value(&self.get_id(name)?)
     }
 
-    /// Serializes the lookup table into a vector of bytes
+    /// Serializes the lookup table into a vector of bytes.
+    /// The entries are sorted by their id so that the output is always the same.
     pub fn serialize(&self) -> Vec<u8> {
         let mut serialized_full: Vec<u8> = Vec::new();
-        for (_, entry) in &self.entries {
-            serialized_full.append(entry.serialize().as_mut())
+        let mut ids: Vec<&u32> = self.entries.keys().collect();
+        ids.sort_unstable();
+        for id in ids {
+            serialized_full.append(self.entries[id].serialize().as_mut())
         }
 
         serialized_full
@@ -308,30 +919,41 @@ impl HashLookupTable {
 }
 
 impl TryFrom<GenericChunk> for HashLookupTable {
-    type Error = Error;
+    type Error = BdfError;
 
-    fn try_from(chunk: GenericChunk) -> Result<HashLookupTable, Error> {
-        if &chunk.name != HTBL_CHUNK_NAME {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                "chunk name doesn't match",
-            ));
+    fn try_from(chunk: GenericChunk) -> Result<HashLookupTable, BdfError> {
+        HashLookupTable::parse_limited(chunk, u32::MAX)
+    }
+}
+
+impl HashLookupTable {
+    /// Parses the lookup table of a `HTBL` chunk and returns an error
+    /// as soon as it contains more than `max_hash_count` hash functions
+    pub fn parse_limited(
+        chunk: GenericChunk,
+        max_hash_count: u32,
+    ) -> Result<HashLookupTable, BdfError> {
+        if chunk.id != ChunkId::HTBL {
+            return Err(BdfError::WrongChunkType {
+                expected: HTBL_CHUNK_NAME.to_string(),
+                found: chunk.id.to_string(),
+            });
         }
         let mut hash_entries: HashMap<u32, HashEntry> = HashMap::new();
         let mut position = 0;
-        while chunk.data.len() > (position + 12) {
-            let id_raw = &chunk.data[position..position + 4];
-            position += 4;
-            let output_length_raw = &chunk.data[position..position + 4];
-            position += 4;
-            let name_length_raw = &chunk.data[position..position + 4];
-            position += 4;
-            let id = BigEndian::read_u32(id_raw);
-            let output_length = BigEndian::read_u32(output_length_raw);
-            let name_length = BigEndian::read_u32(name_length_raw);
-            let name_raw = &chunk.data[position..position + name_length as usize];
-            let name =
-                String::from_utf8(name_raw.to_vec()).expect("Failed to parse hash function name!");
+        while position < chunk.data.len() {
+            if hash_entries.len() as u64 >= max_hash_count as u64 {
+                return Err(BdfError::LimitExceeded {
+                    what: "hash count",
+                    size: hash_entries.len() as u64 + 1,
+                    limit: max_hash_count as u64,
+                });
+            }
+            let id = read_u32(&chunk.data, &mut position)?;
+            let output_length = read_u32(&chunk.data, &mut position)?;
+            let name_length = read_u32(&chunk.data, &mut position)?;
+            let name_raw = read_slice(&chunk.data, &mut position, name_length as usize)?;
+            let name = String::from_utf8(name_raw.to_vec())?;
             hash_entries.insert(
                 id,
                 HashEntry {
@@ -341,14 +963,11 @@ impl TryFrom<GenericChunk> for HashLookupTable {
                 },
             );
         }
-        Ok(HashLookupTable {
-            entries: hash_entries,
-        })
+        Ok(HashLookupTable::new(hash_entries))
     }
 }
 
 impl HashEntry {
-
     /// Creates a new hash entry
     pub fn new(name: String, output_length: u32) -> Self {
         Self {
@@ -378,36 +997,120 @@ impl HashEntry {
 }
 
 impl DataEntry {
-    pub fn new(plain: String) -> Self {
+    /// Creates an entry for the plaintext, which can be a string or raw bytes
+    pub fn new<P: Into<Vec<u8>>>(plain: P) -> Self {
         Self {
-            hashes: HashMap::new(),
-            plain,
+            plain: plain.into(),
+            values: Vec::new(),
+            hashes: Vec::new(),
         }
     }
 
-    /// Adds a hash to the hash values
-    pub fn add_hash_value(&mut self, name: String, value: Vec<u8>) {
-        self.hashes.insert(name, value);
+    /// Returns the plaintext as a string if it is valid UTF-8
+    pub fn plain_str(&self) -> Result<&str, Utf8Error> {
+        std::str::from_utf8(&self.plain)
     }
 
-    /// Returns the hash value for a given name of a hash function
-    pub fn get_hash_value(&self, name: String) -> Option<&Vec<u8>> {
-        self.hashes.get(&name)
+    /// Adds the value of the hash function with the given id
+    /// or replaces its previous value
+    pub fn add_hash(&mut self, hash_id: u32, value: &[u8]) {
+        match self.hashes.binary_search_by_key(&hash_id, |(id, _)| *id) {
+            Ok(index) if self.hashes[index].1.len() == value.len() => {
+                self.values[self.hashes[index].1.clone()].copy_from_slice(value);
+            }
+            Ok(index) => {
+                // the old value is removed so that replacing values doesn't grow the entry
+                let old = self.hashes[index].1.clone();
+                self.values.drain(old.clone());
+                for (_, range) in &mut self.hashes {
+                    if range.start >= old.end {
+                        *range = range.start - old.len()..range.end - old.len();
+                    }
+                }
+                self.hashes[index].1 = self.push_value(value);
+            }
+            Err(index) => {
+                let range = self.push_value(value);
+                self.hashes.insert(index, (hash_id, range));
+            }
+        }
     }
 
-    /// Serializes the entry to a vector of bytes
+    /// Returns the value of the hash function with the given id
+    pub fn hash(&self, hash_id: u32) -> Option<&[u8]> {
+        let index = self
+            .hashes
+            .binary_search_by_key(&hash_id, |(id, _)| *id)
+            .ok()?;
+
+        Some(&self.values[self.hashes[index].1.clone()])
+    }
+
+    /// Returns the hash function ids and hash values sorted by the id
+    pub fn hashes(&self) -> impl Iterator<Item = (u32, &[u8])> {
+        self.hashes
+            .iter()
+            .map(move |(id, range)| (*id, &self.values[range.clone()]))
+    }
+
+    /// Adds the value of the hash function with the given name in the lookup table.
+    /// Returns an error if the lookup table doesn't contain the hash function.
+    pub fn add_hash_value(
+        &mut self,
+        lookup_table: &HashLookupTable,
+        name: &str,
+        value: &[u8],
+    ) -> Result<(), BdfError> {
+        let hash_id = lookup_table
+            .get_id(name)
+            .ok_or_else(|| BdfError::UnknownHashName(name.to_string()))?;
+        self.add_hash(hash_id, value);
+
+        Ok(())
+    }
+
+    /// Returns the value of the hash function with the given name in the lookup table
+    pub fn get_hash_value(&self, lookup_table: &HashLookupTable, name: &str) -> Option<&[u8]> {
+        self.hash(lookup_table.get_id(name)?)
+    }
+
+    /// Appends the value to the buffer and returns its range
+    fn push_value(&mut self, value: &[u8]) -> Range<usize> {
+        let start = self.values.len();
+        self.values.extend_from_slice(value);
+
+        start..self.values.len()
+    }
+
+    /// Returns the length of the serialized entry if all its hash
+    /// functions are in the lookup table
+    pub(crate) fn serialized_len(&self) -> usize {
+        let hash_length: usize = self.hashes.iter().map(|(_, range)| 4 + range.len()).sum();
+
+        8 + self.plain.len() + hash_length
+    }
+
+    /// Decodes a single serialized entry
+    pub fn deserialize(data: &[u8], lookup_table: &HashLookupTable) -> Result<Self, BdfError> {
+        Ok(read_entry(data, &mut 0, lookup_table, u32::MAX)?.to_data_entry())
+    }
+
+    /// Serializes the entry to a vector of bytes.
+    /// The hash values are sorted by the id of their hash function
+    /// so that the output is always the same.
     pub fn serialize(&self, lookup_table: &HashLookupTable) -> Vec<u8> {
-        let mut pw_plain_raw = self.plain.clone().into_bytes();
+        let mut pw_plain_raw = self.plain.clone();
         let mut pw_length_raw = [0u8; 4];
         BigEndian::write_u32(&mut pw_length_raw, pw_plain_raw.len() as u32);
         let mut hash_data: Vec<u8> = Vec::new();
-        for (name, value) in &self.hashes {
-            if let Some((id, _)) = lookup_table.get_entry(&name) {
-                let mut id_raw = [0u8; 4];
-                BigEndian::write_u32(&mut id_raw, *id);
-                hash_data.append(&mut id_raw.to_vec());
-                hash_data.append(&mut value.clone())
+        for (id, value) in self.hashes() {
+            if !lookup_table.entries.contains_key(&id) {
+                continue;
             }
+            let mut id_raw = [0u8; 4];
+            BigEndian::write_u32(&mut id_raw, id);
+            hash_data.extend_from_slice(&id_raw);
+            hash_data.extend_from_slice(value);
         }
 
         let mut length_total_raw = [0u8; 4];
@@ -424,3 +1127,766 @@ impl DataEntry {
         serialized_data
     }
 }
+
+impl<'a> DataEntryRef<'a> {
+    /// Returns the plaintext of the row
+    pub fn plain(&self) -> &'a [u8] {
+        self.plain
+    }
+
+    /// Returns the plaintext as a string if it is valid UTF-8
+    pub fn plain_str(&self) -> Result<&'a str, Utf8Error> {
+        std::str::from_utf8(self.plain)
+    }
+
+    /// Returns the value of the hash function with the given id
+    pub fn hash_value(&self, hash_id: u32) -> Option<&'a [u8]> {
+        self.hash_values()
+            .find(|(id, _)| *id == hash_id)
+            .map(|(_, value)| value)
+    }
+
+    /// Returns an iterator over the hash function ids and values in the order they are stored
+    pub fn hash_values(&self) -> HashValues<'a> {
+        HashValues {
+            hashes: self.hashes,
+            position: 0,
+            lookup_table: self.lookup_table,
+        }
+    }
+
+    /// Copies the row into an owned data entry
+    pub fn to_data_entry(&self) -> DataEntry {
+        let mut entry = DataEntry::new(self.plain);
+        for (hash_id, value) in self.hash_values() {
+            entry.add_hash(hash_id, value);
+        }
+
+        entry
+    }
+}
+
+impl<'a> EntryRefs<'a> {
+    fn new(chunk: &'a GenericChunk, lookup_table: &'a HashLookupTable) -> Self {
+        Self {
+            data: &chunk.data,
+            position: 0,
+            lookup_table,
+            max_entry_size: chunk.max_entry_size,
+            failed: false,
+        }
+    }
+}
+
+impl<'a> Iterator for EntryRefs<'a> {
+    type Item = Result<DataEntryRef<'a>, BdfError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.position >= self.data.len() {
+            return None;
+        }
+        let entry = read_entry(
+            self.data,
+            &mut self.position,
+            self.lookup_table,
+            self.max_entry_size,
+        );
+        self.failed = entry.is_err();
+
+        Some(entry)
+    }
+}
+
+impl<'a> Iterator for HashValues<'a> {
+    type Item = (u32, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // the row was validated when the entry was created, so this only stops at its end
+        let hash_id = read_u32(self.hashes, &mut self.position).ok()?;
+        let output_length = self.lookup_table.entries.get(&hash_id)?.output_length;
+        let value = read_slice(self.hashes, &mut self.position, output_length as usize).ok()?;
+
+        Some((hash_id, value))
+    }
+}
+
+impl ChunkIndex {
+    /// Returns the total number of entries of all indexed chunks
+    pub fn entry_count(&self) -> u64 {
+        self.entries
+            .iter()
+            .map(|entry| entry.entry_count as u64)
+            .sum()
+    }
+
+    /// Returns the number of the chunk containing the nth entry
+    /// and the position of the entry inside that chunk
+    pub fn locate_entry(&self, n: u64) -> Option<(usize, u32)> {
+        let mut first_entry = 0u64;
+        for (chunk_number, entry) in self.entries.iter().enumerate() {
+            let next_first_entry = first_entry + entry.entry_count as u64;
+            if n < next_first_entry {
+                return Some((chunk_number, (n - first_entry) as u32));
+            }
+            first_entry = next_first_entry;
+        }
+
+        None
+    }
+
+    /// Serializes the index into bytes.
+    /// Version 1 stores the chunk lengths as u32, version 2 as u64.
+    /// Returns an error if a chunk length doesn't fit into the version.
+    pub fn serialize(&self, version: FormatVersion) -> Result<Vec<u8>, BdfError> {
+        let mut serialized_data: Vec<u8> = Vec::new();
+        for entry in &self.entries {
+            serialized_data.extend_from_slice(&entry.offset.to_be_bytes());
+            match version {
+                FormatVersion::V1 => {
+                    let length = u32::try_from(entry.length).map_err(|_| BdfError::TooLarge {
+                        what: "indexed chunk",
+                        size: entry.length,
+                        limit: u32::MAX as u64,
+                    })?;
+                    serialized_data.extend_from_slice(&length.to_be_bytes())
+                }
+                FormatVersion::V2 => serialized_data.extend_from_slice(&entry.length.to_be_bytes()),
+            }
+            serialized_data.extend_from_slice(&entry.entry_count.to_be_bytes());
+        }
+
+        Ok(serialized_data)
+    }
+
+    /// Parses a `CIDX` chunk written in the given format version
+    pub fn parse(chunk: GenericChunk, version: FormatVersion) -> Result<ChunkIndex, BdfError> {
+        if chunk.id != ChunkId::CIDX {
+            return Err(BdfError::WrongChunkType {
+                expected: CIDX_CHUNK_NAME.to_string(),
+                found: chunk.id.to_string(),
+            });
+        }
+        let mut entries = Vec::new();
+        let mut position = 0;
+        while position < chunk.data.len() {
+            let offset = read_u64(&chunk.data, &mut position)?;
+            let length = match version {
+                FormatVersion::V1 => read_u32(&chunk.data, &mut position)? as u64,
+                FormatVersion::V2 => read_u64(&chunk.data, &mut position)?,
+            };
+            let entry_count = read_u32(&chunk.data, &mut position)?;
+            entries.push(ChunkIndexEntry {
+                offset,
+                length,
+                entry_count,
+            });
+        }
+
+        Ok(ChunkIndex { entries })
+    }
+}
+
+impl SortIndex {
+    /// Returns the numbers of the data chunks that can contain the given hash value.
+    /// The ranges have to be sorted by their values as they are in a sorted file.
+    pub fn candidate_chunks(&self, hash: &[u8]) -> Vec<u64> {
+        let first = self
+            .ranges
+            .partition_point(|range| range.max.as_slice() < hash);
+
+        self.ranges[first..]
+            .iter()
+            .take_while(|range| range.min.as_slice() <= hash)
+            .filter(|range| range.max.as_slice() >= hash)
+            .map(|range| range.chunk_number)
+            .collect()
+    }
+
+    /// Serializes the sort index into bytes
+    pub fn serialize(&self) -> Vec<u8> {
+        let key_length = self.ranges.first().map_or(0, |range| range.min.len());
+        let mut serialized_data: Vec<u8> = Vec::new();
+        serialized_data.extend_from_slice(&self.hash_id.to_be_bytes());
+        serialized_data.extend_from_slice(&(key_length as u32).to_be_bytes());
+        for range in &self.ranges {
+            serialized_data.extend_from_slice(&range.chunk_number.to_be_bytes());
+            serialized_data.extend_from_slice(&range.min);
+            serialized_data.extend_from_slice(&range.max);
+        }
+
+        serialized_data
+    }
+}
+
+impl TryFrom<GenericChunk> for SortIndex {
+    type Error = BdfError;
+
+    fn try_from(chunk: GenericChunk) -> Result<SortIndex, BdfError> {
+        if chunk.id != ChunkId::SORT {
+            return Err(BdfError::WrongChunkType {
+                expected: SORT_CHUNK_NAME.to_string(),
+                found: chunk.id.to_string(),
+            });
+        }
+        let mut position = 0;
+        let hash_id = read_u32(&chunk.data, &mut position)?;
+        let key_length = read_u32(&chunk.data, &mut position)? as usize;
+        let mut ranges = Vec::new();
+        while position < chunk.data.len() {
+            let chunk_number = read_u64(&chunk.data, &mut position)?;
+            let min = read_slice(&chunk.data, &mut position, key_length)?.to_vec();
+            let max = read_slice(&chunk.data, &mut position, key_length)?.to_vec();
+            ranges.push(KeyRange {
+                chunk_number,
+                min,
+                max,
+            });
+        }
+        // the lookup relies on the order, so it's restored for files
+        // where the ranges aren't stored in the order of their values
+        ranges.sort_by(|a, b| a.min.cmp(&b.min).then_with(|| a.max.cmp(&b.max)));
+
+        Ok(SortIndex { hash_id, ranges })
+    }
+}
+
+/// Returns an error if the false positive rate of a bloom filter isn't strictly between 0 and 1
+pub(crate) fn validate_false_positive_rate(rate: f64) -> Result<(), BdfError> {
+    if rate > 0.0 && rate < 1.0 {
+        Ok(())
+    } else {
+        Err(BdfError::InvalidConfig(format!(
+            "the false positive rate {} isn't between 0 and 1",
+            rate
+        )))
+    }
+}
+
+impl BloomFilter {
+    /// Creates an empty filter sized for the number of entries
+    /// and the given false positive rate.
+    /// Returns an error if the rate isn't strictly between 0 and 1.
+    pub fn new(
+        hash_id: u32,
+        entry_count: usize,
+        false_positive_rate: f64,
+    ) -> Result<Self, BdfError> {
+        validate_false_positive_rate(false_positive_rate)?;
+        let ln2 = std::f64::consts::LN_2;
+        let bit_count =
+            (-(entry_count.max(1) as f64) * false_positive_rate.ln() / (ln2 * ln2)).ceil();
+        let byte_count = (bit_count as usize).div_ceil(8);
+        let hash_count = ((byte_count * 8) as f64 / entry_count.max(1) as f64 * ln2).round();
+
+        Ok(Self {
+            hash_id,
+            hash_count: (hash_count as u32).clamp(1, MAX_BLOOM_HASH_COUNT),
+            bits: vec![0u8; byte_count.max(1)],
+        })
+    }
+
+    /// Adds a hash value to the filter
+    pub fn insert(&mut self, value: &[u8]) {
+        for bit in self.bit_positions(value) {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    /// Returns false if the value is definitely not in the filter.
+    /// A filter without bits doesn't rule out any value.
+    pub fn contains(&self, value: &[u8]) -> bool {
+        if self.bits.is_empty() {
+            return true;
+        }
+        self.bit_positions(value)
+            .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    /// Returns the positions of the bits for the value using double hashing
+    fn bit_positions(&self, value: &[u8]) -> impl Iterator<Item = usize> {
+        let bit_count = self.bits.len() as u64 * 8;
+        let mut first = 0xcbf2_9ce4_8422_2325u64;
+        for byte in value {
+            first = (first ^ *byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+        let mut second = first ^ (first >> 33);
+        second = second.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        second ^= second >> 33;
+        let hash_count = if bit_count == 0 { 0 } else { self.hash_count };
+
+        (0..hash_count as u64)
+            .map(move |i| (first.wrapping_add(i.wrapping_mul(second | 1)) % bit_count) as usize)
+    }
+
+    /// Serializes the filter into bytes
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut serialized_data: Vec<u8> = Vec::new();
+        serialized_data.extend_from_slice(&self.hash_id.to_be_bytes());
+        serialized_data.extend_from_slice(&self.hash_count.to_be_bytes());
+        serialized_data.extend_from_slice(&self.bits);
+
+        serialized_data
+    }
+}
+
+impl TryFrom<GenericChunk> for BloomFilter {
+    type Error = BdfError;
+
+    fn try_from(chunk: GenericChunk) -> Result<BloomFilter, BdfError> {
+        if chunk.id != ChunkId::BLOM {
+            return Err(BdfError::WrongChunkType {
+                expected: BLOM_CHUNK_NAME.to_string(),
+                found: chunk.id.to_string(),
+            });
+        }
+        let mut position = 0;
+        let hash_id = read_u32(&chunk.data, &mut position)?;
+        let hash_count = read_u32(&chunk.data, &mut position)?;
+        if hash_count == 0 || hash_count > MAX_BLOOM_HASH_COUNT {
+            return Err(BdfError::InvalidBloomFilter(hash_count));
+        }
+        let bits = chunk.data[position..].to_vec();
+
+        Ok(BloomFilter {
+            hash_id,
+            hash_count,
+            bits,
+        })
+    }
+}
+
+impl ChunkStats {
+    /// Collects the statistics of the entries of a data chunk
+    pub fn from_entries(entries: &[DataEntry], uncompressed_size: u64) -> Self {
+        let mut ranges: BTreeMap<u32, (&[u8], &[u8])> = BTreeMap::new();
+        for (hash_id, value) in entries.iter().flat_map(DataEntry::hashes) {
+            let range = ranges.entry(hash_id).or_insert((value, value));
+            range.0 = range.0.min(value);
+            range.1 = range.1.max(value);
+        }
+        let plain_lengths = entries.iter().map(|entry| entry.plain.len() as u32);
+
+        ChunkStats {
+            entry_count: entries.len() as u32,
+            uncompressed_size,
+            min_plain_length: plain_lengths.clone().min().unwrap_or(0),
+            max_plain_length: plain_lengths.max().unwrap_or(0),
+            hash_ranges: ranges
+                .into_iter()
+                .map(|(hash_id, (min, max))| HashRange {
+                    hash_id,
+                    min: min.to_vec(),
+                    max: max.to_vec(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the range of the values of the hash function
+    /// or `None` if no entry of the chunk has a value for it
+    pub fn hash_range(&self, hash_id: u32) -> Option<&HashRange> {
+        self.hash_ranges
+            .iter()
+            .find(|range| range.hash_id == hash_id)
+    }
+
+    /// Returns false if no plaintext of the chunk can have a length in the range
+    pub fn may_contain_plain_length(&self, lengths: &RangeInclusive<u32>) -> bool {
+        self.entry_count > 0
+            && *lengths.start() <= self.max_plain_length
+            && self.min_plain_length <= *lengths.end()
+    }
+
+    /// Returns false if no value of the hash function in the chunk can be in the range
+    pub fn may_contain_hashes(&self, hash_id: u32, values: &RangeInclusive<&[u8]>) -> bool {
+        self.hash_range(hash_id).is_some_and(|range| {
+            *values.start() <= range.max.as_slice() && range.min.as_slice() <= *values.end()
+        })
+    }
+
+    /// Serializes the statistics into bytes
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut serialized_data: Vec<u8> = Vec::new();
+        serialized_data.extend_from_slice(&self.entry_count.to_be_bytes());
+        serialized_data.extend_from_slice(&self.uncompressed_size.to_be_bytes());
+        serialized_data.extend_from_slice(&self.min_plain_length.to_be_bytes());
+        serialized_data.extend_from_slice(&self.max_plain_length.to_be_bytes());
+        for range in &self.hash_ranges {
+            serialized_data.extend_from_slice(&range.hash_id.to_be_bytes());
+            serialized_data.extend_from_slice(&(range.min.len() as u32).to_be_bytes());
+            serialized_data.extend_from_slice(&range.min);
+            serialized_data.extend_from_slice(&(range.max.len() as u32).to_be_bytes());
+            serialized_data.extend_from_slice(&range.max);
+        }
+
+        serialized_data
+    }
+}
+
+impl TryFrom<GenericChunk> for ChunkStats {
+    type Error = BdfError;
+
+    fn try_from(chunk: GenericChunk) -> Result<ChunkStats, BdfError> {
+        if chunk.id != ChunkId::STAT {
+            return Err(BdfError::WrongChunkType {
+                expected: STAT_CHUNK_NAME.to_string(),
+                found: chunk.id.to_string(),
+            });
+        }
+        let mut position = 0;
+        let entry_count = read_u32(&chunk.data, &mut position)?;
+        let uncompressed_size = read_u64(&chunk.data, &mut position)?;
+        let min_plain_length = read_u32(&chunk.data, &mut position)?;
+        let max_plain_length = read_u32(&chunk.data, &mut position)?;
+        let mut hash_ranges = Vec::new();
+        while position < chunk.data.len() {
+            let hash_id = read_u32(&chunk.data, &mut position)?;
+            let min_length = read_u32(&chunk.data, &mut position)?;
+            let min = read_slice(&chunk.data, &mut position, min_length as usize)?.to_vec();
+            let max_length = read_u32(&chunk.data, &mut position)?;
+            let max = read_slice(&chunk.data, &mut position, max_length as usize)?.to_vec();
+            hash_ranges.push(HashRange { hash_id, min, max });
+        }
+
+        Ok(ChunkStats {
+            entry_count,
+            uncompressed_size,
+            min_plain_length,
+            max_plain_length,
+            hash_ranges,
+        })
+    }
+}
+
+impl TryFrom<GenericChunk> for ChunkIndexFooter {
+    type Error = BdfError;
+
+    fn try_from(chunk: GenericChunk) -> Result<ChunkIndexFooter, BdfError> {
+        if chunk.id != ChunkId::CFTR {
+            return Err(BdfError::WrongChunkType {
+                expected: CFTR_CHUNK_NAME.to_string(),
+                found: chunk.id.to_string(),
+            });
+        }
+        let index_offset = read_u64(&chunk.data, &mut 0)?;
+
+        Ok(ChunkIndexFooter { index_offset })
+    }
+}
+
+impl InfoChunk {
+    /// Creates an empty info chunk
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the value stored for the key
+    pub fn get(&self, key: &str) -> Option<&InfoValue> {
+        self.entries.get(key)
+    }
+
+    /// Stores the value for the key, replacing any previous value
+    pub fn insert<V: Into<InfoValue>>(&mut self, key: &str, value: V) {
+        self.entries.insert(key.to_string(), value.into());
+    }
+
+    /// Returns true if no value is stored
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Serializes the chunk into bytes
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut serialized_data: Vec<u8> = Vec::new();
+        for (key, value) in &self.entries {
+            serialized_data.extend_from_slice(&(key.len() as u32).to_be_bytes());
+            serialized_data.extend_from_slice(key.as_bytes());
+            let (value_type, value_raw) = value.serialize();
+            serialized_data.push(value_type);
+            serialized_data.extend_from_slice(&(value_raw.len() as u32).to_be_bytes());
+            serialized_data.extend_from_slice(&value_raw);
+        }
+
+        serialized_data
+    }
+}
+
+impl TryFrom<GenericChunk> for InfoChunk {
+    type Error = BdfError;
+
+    fn try_from(chunk: GenericChunk) -> Result<InfoChunk, BdfError> {
+        if chunk.id != ChunkId::INFO {
+            return Err(BdfError::WrongChunkType {
+                expected: INFO_CHUNK_NAME.to_string(),
+                found: chunk.id.to_string(),
+            });
+        }
+        let mut entries = BTreeMap::new();
+        let mut position = 0;
+        while position < chunk.data.len() {
+            let key_length = read_u32(&chunk.data, &mut position)?;
+            let key_raw = read_slice(&chunk.data, &mut position, key_length as usize)?;
+            let key = String::from_utf8(key_raw.to_vec())?;
+            let value_type = read_slice(&chunk.data, &mut position, 1)?[0];
+            let value_length = read_u32(&chunk.data, &mut position)?;
+            let value_raw = read_slice(&chunk.data, &mut position, value_length as usize)?;
+            let value = InfoValue::deserialize(&key, value_type, value_raw)?;
+            entries.insert(key, value);
+        }
+
+        Ok(InfoChunk { entries })
+    }
+}
+
+impl InfoValue {
+    /// Returns the value if it is text
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            InfoValue::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is an integer
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            InfoValue::Integer(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is a float
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            InfoValue::Float(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is a bool
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            InfoValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is a byte string
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            InfoValue::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Returns the type tag and the serialized value
+    fn serialize(&self) -> (u8, Vec<u8>) {
+        match self {
+            InfoValue::Text(text) => (0, text.as_bytes().to_vec()),
+            InfoValue::Integer(number) => (1, number.to_be_bytes().to_vec()),
+            InfoValue::Float(number) => (2, number.to_be_bytes().to_vec()),
+            InfoValue::Bool(value) => (3, vec![*value as u8]),
+            InfoValue::Bytes(bytes) => (4, bytes.clone()),
+        }
+    }
+
+    /// Decodes the value of the key with the given type tag.
+    /// Returns an error if the type is unknown or the value has the wrong length.
+    fn deserialize(key: &str, value_type: u8, raw: &[u8]) -> Result<InfoValue, BdfError> {
+        let invalid = || BdfError::InvalidInfoValue(key.to_string());
+        let value = match value_type {
+            0 => InfoValue::Text(String::from_utf8(raw.to_vec())?),
+            1 => InfoValue::Integer(i64::from_be_bytes(
+                <[u8; 8]>::try_from(raw).map_err(|_| invalid())?,
+            )),
+            2 => InfoValue::Float(f64::from_be_bytes(
+                <[u8; 8]>::try_from(raw).map_err(|_| invalid())?,
+            )),
+            3 => match raw {
+                [0] => InfoValue::Bool(false),
+                [1] => InfoValue::Bool(true),
+                _ => return Err(invalid()),
+            },
+            4 => InfoValue::Bytes(raw.to_vec()),
+            _ => return Err(invalid()),
+        };
+
+        Ok(value)
+    }
+}
+
+impl From<&str> for InfoValue {
+    fn from(text: &str) -> Self {
+        InfoValue::Text(text.to_string())
+    }
+}
+
+impl From<String> for InfoValue {
+    fn from(text: String) -> Self {
+        InfoValue::Text(text)
+    }
+}
+
+impl From<i64> for InfoValue {
+    fn from(number: i64) -> Self {
+        InfoValue::Integer(number)
+    }
+}
+
+impl From<f64> for InfoValue {
+    fn from(number: f64) -> Self {
+        InfoValue::Float(number)
+    }
+}
+
+impl From<bool> for InfoValue {
+    fn from(value: bool) -> Self {
+        InfoValue::Bool(value)
+    }
+}
+
+impl From<Vec<u8>> for InfoValue {
+    fn from(bytes: Vec<u8>) -> Self {
+        InfoValue::Bytes(bytes)
+    }
+}
+
+/// Borrows the data row at the given position and advances it.
+/// Returns an error if the row references unknown hash functions or is too short
+/// for the output lengths of its hash functions.
+fn read_entry<'a>(
+    data: &'a [u8],
+    position: &mut usize,
+    lookup_table: &'a HashLookupTable,
+    max_entry_size: u32,
+) -> Result<DataEntryRef<'a>, BdfError> {
+    let entry_length = read_entry_length(data, position, max_entry_size)?;
+    let row = read_slice(data, position, entry_length as usize)?;
+    let mut row_position = 0;
+    let pw_length = read_u32(row, &mut row_position)?;
+    let plain = read_slice(row, &mut row_position, pw_length as usize)?;
+    let hashes = &row[row_position..];
+
+    let mut hash_position = 0;
+    while hash_position < hashes.len() {
+        let entry_id = read_u32(hashes, &mut hash_position)?;
+        let hash_entry = lookup_table
+            .entries
+            .get(&entry_id)
+            .ok_or(BdfError::UnknownHashId(entry_id))?;
+        read_slice(
+            hashes,
+            &mut hash_position,
+            hash_entry.output_length as usize,
+        )?;
+    }
+
+    Ok(DataEntryRef {
+        plain,
+        hashes,
+        lookup_table,
+    })
+}
+
+/// Checks the hash values of a single row without its length prefix
+fn verify_row(
+    row: &[u8],
+    lookup_table: &HashLookupTable,
+    utf8: bool,
+    chunk_index: u64,
+    entry_number: u32,
+) -> Result<Result<(), Problem>, BdfError> {
+    let mut position = 0;
+    let pw_length = read_u32(row, &mut position)?;
+    let plain = read_slice(row, &mut position, pw_length as usize)?;
+    if utf8 && std::str::from_utf8(plain).is_err() {
+        return Ok(Err(Problem::InvalidPlaintext {
+            chunk_index,
+            entry_number,
+        }));
+    }
+    let mut last_id = None;
+
+    while position < row.len() {
+        let hash_id = match (read_u32(row, &mut position), last_id) {
+            (Ok(hash_id), _) => hash_id,
+            (Err(_), Some(hash_id)) => {
+                return Ok(Err(Problem::HashLengthMismatch {
+                    chunk_index,
+                    entry_number,
+                    hash_id,
+                }))
+            }
+            (Err(err), None) => return Err(err),
+        };
+        let output_length = match lookup_table.entries.get(&hash_id) {
+            Some(entry) => entry.output_length,
+            None => {
+                return Ok(Err(Problem::UnknownHashId {
+                    chunk_index,
+                    entry_number,
+                    hash_id,
+                }))
+            }
+        };
+        if read_slice(row, &mut position, output_length as usize).is_err() {
+            return Ok(Err(Problem::HashLengthMismatch {
+                chunk_index,
+                entry_number,
+                hash_id,
+            }));
+        }
+        last_id = Some(hash_id);
+    }
+
+    Ok(Ok(()))
+}
+
+/// Reads the length of a data row and returns an error if it exceeds the limit
+fn read_entry_length(
+    data: &[u8],
+    position: &mut usize,
+    max_entry_size: u32,
+) -> Result<u32, BdfError> {
+    let entry_length = read_u32(data, position)?;
+    if entry_length > max_entry_size {
+        return Err(BdfError::LimitExceeded {
+            what: "entry size",
+            size: entry_length as u64,
+            limit: max_entry_size as u64,
+        });
+    }
+
+    Ok(entry_length)
+}
+
+/// Returns the number of chunks needed to store the entries
+fn chunk_count_for(entry_count: u64, entries_per_chunk: u32) -> u32 {
+    let entries_per_chunk = entries_per_chunk.max(1) as u64;
+
+    entry_count.div_ceil(entries_per_chunk).min(u32::MAX as u64) as u32
+}
+
+/// Reads a big endian u64 at the given position and advances it
+fn read_u64(data: &[u8], position: &mut usize) -> Result<u64, BdfError> {
+    Ok(BigEndian::read_u64(read_slice(data, position, 8)?))
+}
+
+/// Reads a big endian u32 at the given position and advances it
+fn read_u32(data: &[u8], position: &mut usize) -> Result<u32, BdfError> {
+    Ok(BigEndian::read_u32(read_slice(data, position, 4)?))
+}
+
+/// Returns the slice of the given length at the position and advances it.
+/// Returns an error if the data is too short.
+fn read_slice<'a>(
+    data: &'a [u8],
+    position: &mut usize,
+    length: usize,
+) -> Result<&'a [u8], BdfError> {
+    let end = position.checked_add(length).ok_or(BdfError::Truncated)?;
+    let slice = data.get(*position..end).ok_or(BdfError::Truncated)?;
+    *position = end;
+
+    Ok(slice)
+}
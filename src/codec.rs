@@ -0,0 +1,319 @@
+use crate::chunks::LZMA;
+use crate::error::BdfError;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::Read;
+use std::sync::Arc;
+use xz2::read::{XzDecoder, XzEncoder};
+
+pub const BROTLI: &str = "brot";
+pub const GZIP: &str = "gzip";
+pub const LZ4: &str = "lz4f";
+pub const ZSTD: &str = "zstd";
+
+/// A compression method for the data chunks of a file
+pub trait Codec: Debug + Send + Sync {
+    /// Returns the name that is stored in the compression method field of the `META` chunk.
+    /// The name has to be exactly four bytes long.
+    fn name(&self) -> &str;
+
+    /// Compresses the data with the given level.
+    /// Codecs clamp the level to the range they support.
+    fn compress(&self, data: &[u8], level: u32) -> Result<Vec<u8>, BdfError>;
+
+    /// Decompresses data that was compressed by this codec
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, BdfError>;
+
+    /// Decompresses data that was compressed by this codec and returns an error
+    /// if the decompressed data is longer than `limit` bytes.
+    /// The default implementation checks the length after decompressing all data.
+    fn decompress_limited(&self, data: &[u8], limit: u64) -> Result<Vec<u8>, BdfError> {
+        let decompressed = self.decompress(data)?;
+        check_decompressed_size(decompressed.len() as u64, limit)?;
+
+        Ok(decompressed)
+    }
+
+    /// Decompresses as much of incomplete or damaged data as possible.
+    /// It is used to salvage the rows of a truncated chunk.
+    /// The default implementation only returns data that can be fully decompressed.
+    fn decompress_partial(&self, data: &[u8]) -> Vec<u8> {
+        self.decompress(data).unwrap_or_default()
+    }
+}
+
+/// The codecs known to a reader or writer, keyed by their name
+#[derive(Debug, Clone)]
+pub struct CodecRegistry {
+    codecs: HashMap<String, Arc<dyn Codec>>,
+}
+
+/// lzma compression using xz
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LzmaCodec;
+
+/// zstd compression
+#[cfg(feature = "zstd")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ZstdCodec;
+
+/// lz4 compression using the lz4 frame format. The level is ignored.
+#[cfg(feature = "lz4")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lz4Codec;
+
+/// deflate compression using the gzip format
+#[cfg(feature = "gzip")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GzipCodec;
+
+/// brotli compression
+#[cfg(feature = "brotli")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BrotliCodec;
+
+impl CodecRegistry {
+    /// Creates a registry without any codecs
+    pub fn empty() -> Self {
+        Self {
+            codecs: HashMap::new(),
+        }
+    }
+
+    /// Adds a codec to the registry, replacing any codec with the same name.
+    /// Returns an error if the name of the codec isn't four bytes long.
+    pub fn register(&mut self, codec: Arc<dyn Codec>) -> Result<(), BdfError> {
+        validate_codec_name(codec.name())?;
+        self.insert(codec);
+
+        Ok(())
+    }
+
+    fn insert(&mut self, codec: Arc<dyn Codec>) {
+        self.codecs.insert(codec.name().to_string(), codec);
+    }
+
+    /// Returns the codec with the given name
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Codec>> {
+        self.codecs.get(name).cloned()
+    }
+
+    /// Returns the sorted names of all registered codecs
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.codecs.keys().map(String::as_str).collect();
+        names.sort_unstable();
+
+        names
+    }
+}
+
+impl Default for CodecRegistry {
+    /// Creates a registry containing lzma and all codecs enabled by cargo features
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.insert(Arc::new(LzmaCodec));
+        #[cfg(feature = "zstd")]
+        registry.insert(Arc::new(ZstdCodec));
+        #[cfg(feature = "lz4")]
+        registry.insert(Arc::new(Lz4Codec));
+        #[cfg(feature = "gzip")]
+        registry.insert(Arc::new(GzipCodec));
+        #[cfg(feature = "brotli")]
+        registry.insert(Arc::new(BrotliCodec));
+
+        registry
+    }
+}
+
+/// Reads from the decoder and stops as soon as more than `limit` bytes were decompressed
+fn read_limited<R: Read>(decoder: R, limit: u64) -> Result<Vec<u8>, BdfError> {
+    let mut decompressed = Vec::new();
+    decoder
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut decompressed)?;
+    check_decompressed_size(decompressed.len() as u64, limit)?;
+
+    Ok(decompressed)
+}
+
+/// Returns an error if the decompressed size exceeds the limit
+fn check_decompressed_size(size: u64, limit: u64) -> Result<(), BdfError> {
+    if size > limit {
+        Err(BdfError::LimitExceeded {
+            what: "decompressed chunk size",
+            size,
+            limit,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Reads from the decoder until the end of the data or the first error
+fn read_partial<R: Read>(mut decoder: R) -> Vec<u8> {
+    let mut decompressed = Vec::new();
+    let mut buffer = [0u8; 8192];
+    while let Ok(read) = decoder.read(&mut buffer) {
+        if read == 0 {
+            break;
+        }
+        decompressed.extend_from_slice(&buffer[..read]);
+    }
+
+    decompressed
+}
+
+/// Returns an error if the name can't be stored in the `META` chunk
+pub(crate) fn validate_codec_name(name: &str) -> Result<(), BdfError> {
+    if name.len() == 4 && name.as_bytes() != [0u8; 4] {
+        Ok(())
+    } else {
+        Err(BdfError::UnsupportedCompression(name.to_string()))
+    }
+}
+
+impl Codec for LzmaCodec {
+    fn name(&self) -> &str {
+        LZMA
+    }
+
+    fn compress(&self, data: &[u8], level: u32) -> Result<Vec<u8>, BdfError> {
+        let mut compressed = Vec::new();
+        XzEncoder::new(data, level.min(9)).read_to_end(&mut compressed)?;
+
+        Ok(compressed)
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, BdfError> {
+        let mut decompressed = Vec::new();
+        XzDecoder::new(data).read_to_end(&mut decompressed)?;
+
+        Ok(decompressed)
+    }
+
+    fn decompress_limited(&self, data: &[u8], limit: u64) -> Result<Vec<u8>, BdfError> {
+        read_limited(XzDecoder::new(data), limit)
+    }
+
+    fn decompress_partial(&self, data: &[u8]) -> Vec<u8> {
+        read_partial(XzDecoder::new(data))
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Codec for ZstdCodec {
+    fn name(&self) -> &str {
+        ZSTD
+    }
+
+    fn compress(&self, data: &[u8], level: u32) -> Result<Vec<u8>, BdfError> {
+        Ok(zstd::stream::encode_all(data, level.clamp(1, 22) as i32)?)
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, BdfError> {
+        Ok(zstd::stream::decode_all(data)?)
+    }
+
+    fn decompress_limited(&self, data: &[u8], limit: u64) -> Result<Vec<u8>, BdfError> {
+        read_limited(zstd::stream::read::Decoder::new(data)?, limit)
+    }
+
+    fn decompress_partial(&self, data: &[u8]) -> Vec<u8> {
+        zstd::stream::read::Decoder::new(data)
+            .map(read_partial)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(feature = "lz4")]
+impl Codec for Lz4Codec {
+    fn name(&self) -> &str {
+        LZ4
+    }
+
+    fn compress(&self, data: &[u8], _level: u32) -> Result<Vec<u8>, BdfError> {
+        use std::io::Write;
+
+        let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+        encoder.write_all(data)?;
+
+        encoder
+            .finish()
+            .map_err(|err| BdfError::Io(std::io::Error::other(err)))
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, BdfError> {
+        let mut decompressed = Vec::new();
+        lz4_flex::frame::FrameDecoder::new(data).read_to_end(&mut decompressed)?;
+
+        Ok(decompressed)
+    }
+
+    fn decompress_limited(&self, data: &[u8], limit: u64) -> Result<Vec<u8>, BdfError> {
+        read_limited(lz4_flex::frame::FrameDecoder::new(data), limit)
+    }
+
+    fn decompress_partial(&self, data: &[u8]) -> Vec<u8> {
+        read_partial(lz4_flex::frame::FrameDecoder::new(data))
+    }
+}
+
+#[cfg(feature = "gzip")]
+impl Codec for GzipCodec {
+    fn name(&self) -> &str {
+        GZIP
+    }
+
+    fn compress(&self, data: &[u8], level: u32) -> Result<Vec<u8>, BdfError> {
+        let mut compressed = Vec::new();
+        flate2::read::GzEncoder::new(data, flate2::Compression::new(level.min(9)))
+            .read_to_end(&mut compressed)?;
+
+        Ok(compressed)
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, BdfError> {
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(data).read_to_end(&mut decompressed)?;
+
+        Ok(decompressed)
+    }
+
+    fn decompress_limited(&self, data: &[u8], limit: u64) -> Result<Vec<u8>, BdfError> {
+        read_limited(flate2::read::GzDecoder::new(data), limit)
+    }
+
+    fn decompress_partial(&self, data: &[u8]) -> Vec<u8> {
+        read_partial(flate2::read::GzDecoder::new(data))
+    }
+}
+
+#[cfg(feature = "brotli")]
+impl Codec for BrotliCodec {
+    fn name(&self) -> &str {
+        BROTLI
+    }
+
+    fn compress(&self, data: &[u8], level: u32) -> Result<Vec<u8>, BdfError> {
+        let mut compressed = Vec::new();
+        brotli::CompressorReader::new(data, 4096, level.min(11), 22)
+            .read_to_end(&mut compressed)?;
+
+        Ok(compressed)
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, BdfError> {
+        let mut decompressed = Vec::new();
+        brotli::Decompressor::new(data, 4096).read_to_end(&mut decompressed)?;
+
+        Ok(decompressed)
+    }
+
+    fn decompress_limited(&self, data: &[u8], limit: u64) -> Result<Vec<u8>, BdfError> {
+        read_limited(brotli::Decompressor::new(data, 4096), limit)
+    }
+
+    fn decompress_partial(&self, data: &[u8]) -> Vec<u8> {
+        read_partial(brotli::Decompressor::new(data, 4096))
+    }
+}
@@ -0,0 +1,156 @@
+use crate::chunks::ChunkId;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::string::FromUtf8Error;
+
+/// The error type returned by every fallible operation of the library
+#[derive(Debug)]
+pub enum BdfError {
+    /// An error of the underlying reader or writer
+    Io(io::Error),
+    /// The file doesn't start with a valid BDF header
+    InvalidHeader,
+    /// The crc sum stored in a chunk doesn't match its data
+    CrcMismatch {
+        chunk_index: u64,
+        expected: u32,
+        actual: u32,
+    },
+    /// A data row references a hash function that isn't in the lookup table
+    UnknownHashId(u32),
+    /// The lookup table doesn't contain a hash function with this name
+    UnknownHashName(String),
+    /// A data entry doesn't have a value for the hash function
+    MissingHashValue(String),
+    /// The data entries aren't sorted by the hash function
+    NotSortedBy(String),
+    /// The file was written in a newer version of the format
+    UnsupportedVersion(u8),
+    /// The compression method stored in the metadata isn't supported
+    UnsupportedCompression(String),
+    /// The data ended in the middle of a chunk or entry
+    Truncated,
+    /// A chunk of a different type was expected
+    WrongChunkType { expected: String, found: String },
+    /// The file contains a critical chunk of a type the reader doesn't know
+    UnknownChunk(ChunkId),
+    /// A chunk type isn't four ASCII letters or is reserved for the chunks of the format
+    InvalidChunkId(String),
+    /// The value of the key in the `INFO` chunk has an unknown type or an invalid length
+    InvalidInfoValue(String),
+    /// A `BLOM` chunk uses no or more than [`MAX_BLOOM_HASH_COUNT`] hash functions
+    ///
+    /// [`MAX_BLOOM_HASH_COUNT`]: crate::chunks::MAX_BLOOM_HASH_COUNT
+    InvalidBloomFilter(u32),
+    /// A string field doesn't contain valid UTF-8
+    InvalidUtf8(FromUtf8Error),
+    /// The head of the file was already written and can't be changed anymore
+    HeadAlreadyWritten,
+    /// A worker thread stopped before delivering its result
+    WorkerFailed,
+    /// The worker threads were already started and can't be configured anymore
+    WorkersStarted,
+    /// A reader or writer was configured with invalid or conflicting options
+    InvalidConfig(String),
+    /// The requested chunk or entry doesn't exist
+    OutOfRange(u64),
+    /// A size or count read from the file exceeds a limit of the reader
+    LimitExceeded {
+        what: &'static str,
+        size: u64,
+        limit: u64,
+    },
+    /// A chunk or entry is too large to be stored in the format version
+    TooLarge {
+        what: &'static str,
+        size: u64,
+        limit: u64,
+    },
+}
+
+impl Display for BdfError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BdfError::Io(err) => write!(f, "io error: {}", err),
+            BdfError::InvalidHeader => write!(f, "invalid BDF header"),
+            BdfError::CrcMismatch {
+                chunk_index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "crc mismatch in chunk {}: expected {:#010x}, got {:#010x}",
+                chunk_index, expected, actual
+            ),
+            BdfError::UnknownHashId(id) => write!(f, "unknown hash id {}", id),
+            BdfError::UnknownHashName(name) => write!(f, "unknown hash function {:?}", name),
+            BdfError::MissingHashValue(name) => {
+                write!(
+                    f,
+                    "the data entry has no value for the hash function {:?}",
+                    name
+                )
+            }
+            BdfError::NotSortedBy(name) => {
+                write!(
+                    f,
+                    "the data entries aren't sorted by the hash function {:?}",
+                    name
+                )
+            }
+            BdfError::UnsupportedVersion(version) => {
+                write!(f, "unsupported format version {}", version)
+            }
+            BdfError::UnsupportedCompression(method) => {
+                write!(f, "unsupported compression method {:?}", method)
+            }
+            BdfError::Truncated => write!(f, "unexpected end of data"),
+            BdfError::WrongChunkType { expected, found } => {
+                write!(f, "expected a {} chunk but found {}", expected, found)
+            }
+            BdfError::UnknownChunk(id) => write!(f, "unknown critical chunk {}", id),
+            BdfError::InvalidChunkId(name) => write!(f, "invalid chunk type {:?}", name),
+            BdfError::InvalidInfoValue(key) => write!(f, "invalid info value for {:?}", key),
+            BdfError::InvalidBloomFilter(hash_count) => {
+                write!(f, "invalid bloom filter with {} hash functions", hash_count)
+            }
+            BdfError::InvalidUtf8(err) => write!(f, "invalid utf-8 string: {}", err),
+            BdfError::HeadAlreadyWritten => write!(f, "the head has already been written"),
+            BdfError::WorkerFailed => write!(f, "a worker thread failed"),
+            BdfError::WorkersStarted => write!(f, "the worker threads have already been started"),
+            BdfError::InvalidConfig(reason) => write!(f, "invalid configuration: {}", reason),
+            BdfError::OutOfRange(n) => write!(f, "there is no chunk or entry number {}", n),
+            BdfError::LimitExceeded { what, size, limit } => {
+                write!(f, "the {} of {} exceeds the limit of {}", what, size, limit)
+            }
+            BdfError::TooLarge { what, size, limit } => write!(
+                f,
+                "the {} of {} bytes exceeds the maximum of {} bytes the format version allows",
+                what, size, limit
+            ),
+        }
+    }
+}
+
+impl Error for BdfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BdfError::Io(err) => Some(err),
+            BdfError::InvalidUtf8(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BdfError {
+    fn from(err: io::Error) -> Self {
+        BdfError::Io(err)
+    }
+}
+
+impl From<FromUtf8Error> for BdfError {
+    fn from(err: FromUtf8Error) -> Self {
+        BdfError::InvalidUtf8(err)
+    }
+}
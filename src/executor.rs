@@ -0,0 +1,31 @@
+use std::fmt::Debug;
+
+/// A job that compresses or decompresses a single chunk
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Runs the compression and decompression jobs of readers and writers
+/// on threads owned by the caller instead of private worker threads
+pub trait Executor: Debug + Send + Sync {
+    /// Runs the job on one of the threads of the executor.
+    /// Every job has to be run eventually, otherwise the reader or writer waits forever.
+    fn execute(&self, job: Job);
+}
+
+/// Runs the jobs on the global rayon thread pool
+#[cfg(feature = "rayon")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RayonGlobalPool;
+
+#[cfg(feature = "rayon")]
+impl Executor for rayon::ThreadPool {
+    fn execute(&self, job: Job) {
+        self.spawn(job);
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl Executor for RayonGlobalPool {
+    fn execute(&self, job: Job) {
+        rayon::spawn(job);
+    }
+}
@@ -1,51 +1,291 @@
 use super::chunks::*;
+use crate::codec::{validate_codec_name, Codec, CodecRegistry, LzmaCodec};
+use crate::error::BdfError;
+use crate::executor::Executor;
+use crate::registry::{validate_chunk_id, ChunkDecoder, ChunkRegistry};
+use crate::salvage::{Loss, SalvageReport};
+use crate::sort::ExternalSorter;
+use crate::verify::{Problem, VerifyReport};
 use byteorder::{BigEndian, ByteOrder};
-use std::collections::HashMap;
-use std::convert::TryInto;
+use crossbeam_channel::{bounded, Receiver, Sender};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::{TryFrom, TryInto};
+use std::fmt::{self, Debug, Formatter};
 use std::fs::File;
-use std::io::Error;
-use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
-use std::thread;
-use crossbeam_channel::{bounded, Sender, Receiver};
-use crossbeam_utils::sync::WaitGroup;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::ops::RangeInclusive;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
 
 const ENTRIES_PER_CHUNK: u32 = 100_000;
+const SORT_BUFFER_SIZE: usize = 1_000_000;
+const SALVAGE_WINDOW: usize = 64 * 1024;
+const MAX_CHUNK_SIZE: u64 = 1 << 30;
+const MAX_ENTRY_SIZE: u32 = 16 << 20;
+const MAX_HASH_COUNT: u32 = 4096;
 
-#[derive(Debug)]
 struct ThreadManager<T1, T2> {
     pub sender_work: Sender<T1>,
     pub receiver_work: Receiver<T1>,
     pub sender_result: Sender<T2>,
     pub receiver_result: Receiver<T2>,
-    pub wg: WaitGroup,
+    pub handles: Vec<JoinHandle<()>>,
     pub threads_started: bool,
+    executor: Option<Arc<dyn Executor>>,
+    work: Option<Arc<WorkFn<T1, T2>>>,
 }
 
+/// The function the workers apply to every piece of work
+type WorkFn<T1, T2> = dyn Fn(T1) -> T2 + Send + Sync;
+
 #[derive(Debug)]
-pub struct BDFReader {
-    reader: BufReader<File>,
+pub struct BDFReader<R = File> {
+    reader: BufReader<R>,
     pub metadata: Option<MetaChunk>,
     pub lookup_table: Option<HashLookupTable>,
-    compressed: bool,
-    thread_manager: ThreadManager<GenericChunk, GenericChunk>,
+    info: Option<InfoChunk>,
+    codec: Option<Arc<dyn Codec>>,
+    codecs: CodecRegistry,
+    chunk_types: ChunkRegistry,
+    chunk_index: u64,
+    thread_manager: ThreadManager<DecompressionJob, DecompressedChunk>,
+    threads: usize,
+    executor: Option<Arc<dyn Executor>>,
+    pending: BTreeMap<u64, Option<PendingChunk>>,
+    next_index: u64,
+    eof: bool,
+    prefetch: usize,
+    offset: u64,
+    data_start: u64,
+    data_start_chunk: u64,
+    seek: Option<SeekFn<R>>,
+    index: Option<ChunkIndex>,
+    data_chunks_read: u64,
+    query: Option<Arc<HashQuery>>,
+    query_matches: Option<QueryMatches>,
+    skip_next_data: bool,
+    skipped_chunks: HashSet<u64>,
+    stats_filter: Option<StatsFilter>,
+    limits: ReadLimits,
+    validation: Validation,
+    checksum: Checksum,
+    format_version: Option<FormatVersion>,
+}
+
+/// Moves the buffered inner reader of a seekable [`BDFReader`] to the given offset
+type SeekFn<R> = fn(&mut BufReader<R>, u64) -> std::io::Result<()>;
+
+/// A running hash lookup. The worker threads match the data chunks
+/// against it after decompressing them.
+#[derive(Debug)]
+struct HashQuery {
+    lookup_table: HashLookupTable,
+    hash_id: u32,
+    targets: HashSet<Vec<u8>>,
+}
+
+/// The entries of a data chunk that match the running hash lookup with their position
+type QueryMatches = Result<Vec<(u32, DataEntry)>, BdfError>;
+/// A chunk that is sent to the decompression workers with the running hash lookup
+type DecompressionJob = (GenericChunk, Option<Arc<HashQuery>>);
+/// The decompressed chunk returned by the workers with the matches of the hash lookup
+type DecompressedChunk = (u64, Result<GenericChunk, BdfError>, Option<QueryMatches>);
+/// A chunk that is ready to be returned in order with the matches of the hash lookup
+type PendingChunk = (Result<GenericChunk, BdfError>, Option<QueryMatches>);
+
+/// How strictly a reader checks the chunks it reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Validation {
+    /// Only the crc sums of compressed data chunks are checked when they are decompressed
+    #[default]
+    Lenient,
+    /// The crc sum of every chunk is checked
+    Strict,
+}
+
+/// Conditions checked against the `stat` chunks of a file.
+/// Data chunks whose statistics rule out every entry are skipped without decompressing them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatsFilter {
+    /// Keeps data chunks that can contain plaintexts with a length in the range
+    pub plain_length: Option<RangeInclusive<u32>>,
+    /// Keeps data chunks that can contain values of the hash function in the range
+    pub hash_range: Option<HashRange>,
+}
+
+/// Limits for sizes and counts read from a file.
+/// Files exceeding them are rejected with [`BdfError::LimitExceeded`]
+/// instead of making the reader allocate huge amounts of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadLimits {
+    /// The maximum length of the stored and of the decompressed data of a chunk
+    pub max_chunk_size: u64,
+    /// The maximum length of a single data row
+    pub max_entry_size: u32,
+    /// The maximum number of hash functions in the lookup table
+    pub max_hash_count: u32,
+}
+
+/// A data entry found by a hash lookup
+#[derive(Debug, Clone)]
+pub struct HashMatch {
+    /// The number of the data chunk containing the entry
+    pub chunk_number: u64,
+    /// The position of the entry inside the data chunk
+    pub entry_number: u32,
+    pub entry: DataEntry,
 }
 
+/// Iterator over the chunks of a file, created by [`BDFReader::chunks`]
 #[derive(Debug)]
-pub struct BDFWriter {
-    writer: BufWriter<File>,
+pub struct Chunks<'a, R> {
+    reader: &'a mut BDFReader<R>,
+    finished: bool,
+}
+
+/// Iterator over the data entries of a file, created by [`BDFReader::entries`]
+#[derive(Debug)]
+pub struct Entries<'a, R> {
+    chunks: Chunks<'a, R>,
+    lookup_table: Option<HashLookupTable>,
+    current: std::vec::IntoIter<DataEntry>,
+}
+
+/// Iterator over the data entries that can be recovered from a damaged file,
+/// created by [`BDFReader::salvage`]
+#[derive(Debug)]
+pub struct Salvage<'a, R> {
+    reader: &'a mut BDFReader<R>,
+    lookup_table: Option<HashLookupTable>,
+    offset: u64,
+    end: u64,
+    current: std::vec::IntoIter<DataEntry>,
+    finished: bool,
+    report: SalvageReport,
+}
+
+/// The state of the chunk starting at an offset of a damaged file
+#[derive(Debug)]
+enum SalvagedChunk {
+    /// The crc sum of the chunk matches, data chunks are decompressed.
+    /// Contains the chunk and the length stored in its header.
+    Intact(GenericChunk, u64),
+    /// The header is plausible but the data or crc sum is wrong
+    Damaged(String, BdfError),
+    /// The header is plausible but the file ends inside the chunk.
+    /// Contains the name and the length stored in the header.
+    Truncated(String, u64),
+    /// There is no plausible chunk header
+    Invalid,
+}
+
+#[derive(Debug)]
+pub struct BDFWriter<W: Write = File> {
+    writer: BufWriter<W>,
     metadata: MetaChunk,
     lookup_table: HashLookupTable,
+    info: InfoChunk,
+    /// Custom chunks that are written after the lookup table
+    head_chunks: Vec<GenericChunk>,
     data_entries: Vec<DataEntry>,
     head_written: bool,
-    compressed: bool,
+    finished: bool,
+    codec: Option<Arc<dyn Codec>>,
     compression_level: u32,
-    thread_manager: ThreadManager<GenericChunk, Vec<u8>>,
+    thread_manager: ThreadManager<CompressionJob, CompressedChunk>,
+    /// Serialized chunks that are waiting for the chunks before them, keyed by sequence number
+    pending: BTreeMap<u64, (ChunkInfo, Vec<u8>)>,
+    next_sequence: u64,
+    next_write: u64,
+    max_in_flight: usize,
+    executor: Option<Arc<dyn Executor>>,
+    bytes_per_chunk: Option<u64>,
+    chunk_size: u64,
+    offset: u64,
+    write_index: bool,
+    chunk_index: ChunkIndex,
+    sort_buffer_size: usize,
+    sorter: Option<ExternalSorter>,
+    sort_index: Option<SortIndex>,
+    bloom_filter: Option<(String, f64)>,
+    write_stats: bool,
+    meta_counts: MetaCounts<W>,
+    requested_version: Option<FormatVersion>,
+    format_version: FormatVersion,
+    checksum: Checksum,
+}
+
+/// How the counts in the `META` chunk are stored
+#[derive(Debug)]
+enum MetaCounts<W> {
+    /// The counts passed to the constructor are written at the start
+    Fixed,
+    /// The `META` chunk at the start is overwritten with the counts by the function
+    Patch(fn(&mut W, u64, &[u8]) -> std::io::Result<()>),
+    /// A second `META` chunk with the counts is written at the end
+    Trailer,
+}
+
+/// A data chunk with its sequence number that is sent to the compression workers
+type CompressionJob = (u64, GenericChunk, ChunkInfo);
+/// The serialized chunk or the error returned by the compression workers
+type CompressedChunk = (u64, ChunkInfo, Result<Vec<u8>, BdfError>);
+
+/// Information about a data chunk that is collected before it is compressed
+#[derive(Debug, Clone, Default)]
+struct ChunkInfo {
+    entry_count: u32,
+    key_range: Option<(Vec<u8>, Vec<u8>)>,
+    bloom_filter: Option<BloomFilter>,
+    stats: Option<ChunkStats>,
+}
+
+impl StatsFilter {
+    /// Returns false if the statistics rule out every entry of the data chunk
+    pub fn matches(&self, stats: &ChunkStats) -> bool {
+        let lengths_match = self
+            .plain_length
+            .as_ref()
+            .is_none_or(|lengths| stats.may_contain_plain_length(lengths));
+        let hashes_match = self.hash_range.as_ref().is_none_or(|range| {
+            stats.may_contain_hashes(
+                range.hash_id,
+                &(range.min.as_slice()..=range.max.as_slice()),
+            )
+        });
+
+        lengths_match && hashes_match
+    }
+}
+
+impl Default for ReadLimits {
+    fn default() -> Self {
+        Self {
+            max_chunk_size: MAX_CHUNK_SIZE,
+            max_entry_size: MAX_ENTRY_SIZE,
+            max_hash_count: MAX_HASH_COUNT,
+        }
+    }
+}
+
+impl ReadLimits {
+    /// Returns limits that accept every file
+    pub fn unlimited() -> Self {
+        Self {
+            max_chunk_size: u64::MAX,
+            max_entry_size: u32::MAX,
+            max_hash_count: u32::MAX,
+        }
+    }
 }
 
 impl<T1, T2> ThreadManager<T1, T2> {
     /// Creates a new thread manager to store channels and information
-    /// about threads to control them
-    pub fn new(cap: usize) -> Self {
+    /// about threads to control them.
+    /// If an executor is passed the work is run on it instead of private threads.
+    pub fn new(cap: usize, executor: Option<Arc<dyn Executor>>) -> Self {
         let (s1, r1) = bounded(cap);
         let (s2, r2) = bounded(cap);
         Self {
@@ -53,8 +293,10 @@ impl<T1, T2> ThreadManager<T1, T2> {
             receiver_work: r1,
             sender_result: s2,
             receiver_result: r2,
-            wg: WaitGroup::new(),
+            handles: Vec::new(),
             threads_started: false,
+            executor,
+            work: None,
         }
     }
 
@@ -69,20 +311,113 @@ impl<T1, T2> ThreadManager<T1, T2> {
     /// Drops the receiver
     pub fn drop_sender_result(&mut self) {
         let sender = self.sender_result.clone();
-        let (s2,_) = bounded(0);
+        let (s2, _) = bounded(0);
         self.sender_result = s2;
         drop(sender);
     }
 
-    /// Waits for the wait group
-    pub fn wait(&mut self) {
-        let wg = self.wg.clone();
-        self.wg = WaitGroup::new();
-        wg.wait();
+    /// Waits for all worker threads to stop.
+    /// Returns an error if one of them panicked.
+    pub fn wait(&mut self) -> Result<(), BdfError> {
+        let mut result = Ok(());
+        for handle in self.handles.drain(..) {
+            if handle.join().is_err() {
+                result = Err(BdfError::WorkerFailed);
+            }
+        }
+
+        result
+    }
+
+    /// Stops the worker threads without waiting for the queued work.
+    /// Workers finish the job they are working on and exit
+    /// because the work channel is closed and their results can't be sent.
+    pub fn shutdown(&mut self) {
+        while self.receiver_work.try_recv().is_ok() {}
+        self.drop_sender();
+        let (_, r2) = bounded(0);
+        self.receiver_result = r2;
+        let _ = self.wait();
+    }
+}
+
+impl<T1: Send + 'static, T2: Send + 'static> ThreadManager<T1, T2> {
+    /// Starts the given number of worker threads that apply the function to the work.
+    /// If the work runs on an executor only the function is stored.
+    pub fn start<F>(&mut self, threads: usize, work: F)
+    where
+        F: Fn(T1) -> T2 + Send + Sync + 'static,
+    {
+        let work = Arc::new(work);
+        if self.executor.is_none() {
+            for _ in 0..threads.max(1) {
+                let r = self.receiver_work.clone();
+                let s = self.sender_result.clone();
+                let work = Arc::clone(&work);
+                self.handles.push(thread::spawn(move || {
+                    for item in r {
+                        if s.send(work(item)).is_err() {
+                            break;
+                        }
+                    }
+                }));
+            }
+            self.drop_sender_result();
+        }
+        self.work = Some(work);
+        self.threads_started = true;
+    }
+
+    /// Hands the work to the worker threads or runs it on the executor
+    pub fn send(&self, item: T1) -> Result<(), BdfError> {
+        match (&self.executor, &self.work) {
+            (Some(executor), Some(work)) => {
+                let s = self.sender_result.clone();
+                let work = Arc::clone(work);
+                executor.execute(Box::new(move || {
+                    let _ = s.send(work(item));
+                }));
+
+                Ok(())
+            }
+            _ => self
+                .sender_work
+                .send(item)
+                .map_err(|_| BdfError::WorkerFailed),
+        }
+    }
+}
+
+impl<T1, T2> Debug for ThreadManager<T1, T2> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ThreadManager")
+            .field("threads", &self.handles.len())
+            .field("threads_started", &self.threads_started)
+            .field("executor", &self.executor)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T1, T2> Drop for ThreadManager<T1, T2> {
+    /// Shuts the worker threads of a reader or writer down when it is dropped
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl BDFWriter<File> {
+    /// Creates the file at the given path and returns a BDFWriter for it.
+    /// See [`BDFWriter::new`] for the meaning of the arguments.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        entry_count: u64,
+        compress: bool,
+    ) -> Result<Self, BdfError> {
+        Ok(Self::new(File::create(path)?, entry_count, compress))
     }
 }
 
-impl BDFWriter {
+impl<W: Write> BDFWriter<W> {
     /// Creates a new BDFWriter.
     /// The number for `entry_count` should be the total number of entries
     /// This is required since the META chunk containing the information is the
@@ -90,295 +425,2048 @@ impl BDFWriter {
     /// The number of entries can be used in tools that provide a progress
     /// bar for how many entries were read.
     /// If the `compress` parameter is true, each data chunk will be compressed
-    /// using lzma with a default level of 1.
-    pub fn new(inner: File, entry_count: u64, compress: bool) -> Self {
+    /// using lzma with a default level of 1. Other codecs can be selected with
+    /// [`BDFWriter::set_compression_method`] or [`BDFWriter::set_codec`].
+    pub fn new(inner: W, entry_count: u64, compress: bool) -> Self {
+        Self::with_metadata(
+            inner,
+            MetaChunk::new(entry_count, ENTRIES_PER_CHUNK, compress),
+            MetaCounts::Fixed,
+        )
+    }
+
+    /// Creates a new BDFWriter for outputs that can't seek when the number
+    /// of entries isn't known in advance.
+    /// The `META` chunk at the start of the file stores [`UNKNOWN_ENTRY_COUNT`]
+    /// and a second `META` chunk with the real counts is written by [`BDFWriter::finish`].
+    pub fn new_streaming(inner: W, compress: bool) -> Self {
+        Self::with_metadata(
+            inner,
+            MetaChunk::with_unknown_counts(ENTRIES_PER_CHUNK, compress),
+            MetaCounts::Trailer,
+        )
+    }
+
+    fn with_metadata(inner: W, metadata: MetaChunk, meta_counts: MetaCounts<W>) -> Self {
+        let compress = metadata.compression_method.is_some();
         Self {
-            metadata: MetaChunk::new(entry_count, ENTRIES_PER_CHUNK, compress),
+            metadata,
             lookup_table: HashLookupTable::new(HashMap::new()),
+            info: InfoChunk::new(),
+            head_chunks: Vec::new(),
             data_entries: Vec::new(),
             writer: BufWriter::new(inner),
             head_written: false,
-            compressed: compress,
+            finished: false,
+            codec: if compress {
+                Some(Arc::new(LzmaCodec))
+            } else {
+                None
+            },
             compression_level: 1,
-            thread_manager: ThreadManager::new(num_cpus::get()),
+            thread_manager: ThreadManager::new(0, None),
+            pending: BTreeMap::new(),
+            next_sequence: 0,
+            next_write: 0,
+            max_in_flight: num_cpus::get(),
+            executor: None,
+            bytes_per_chunk: None,
+            chunk_size: 0,
+            offset: 0,
+            write_index: false,
+            chunk_index: ChunkIndex::default(),
+            sort_buffer_size: SORT_BUFFER_SIZE,
+            sorter: None,
+            sort_index: None,
+            bloom_filter: None,
+            write_stats: false,
+            meta_counts,
+            requested_version: None,
+            format_version: FormatVersion::default(),
+            checksum: Checksum::default(),
         }
     }
 
     /// Starts threads for parallel chunk compression
-    fn start_threads(&self) {
-        for _ in 0..num_cpus::get() {
-            let compress = self.compressed;
-            let compression_level = self.compression_level;
-            thread::spawn({
-                let r = self.thread_manager.receiver_work.clone();
-                let s = self.thread_manager.sender_result.clone();
-                let wg: WaitGroup = self.thread_manager.wg.clone();
-                move || {
-                    for mut chunk in r {
-                        if compress {
-                            chunk.compress(compression_level).expect("failed to compress chunk");
-                        }
-                        s.send(chunk.serialize()).expect("failed to send result");
+    fn start_threads(&mut self) {
+        let codec = self.codec.clone();
+        let compression_level = self.compression_level;
+        let format_version = self.format_version;
+        let checksum = self.checksum;
+        self.thread_manager = ThreadManager::new(self.max_in_flight, self.executor.clone());
+        self.thread_manager
+            .start(self.max_in_flight, move |(sequence, mut chunk, info)| {
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    chunk.crc = checksum.compute(&chunk.data);
+                    if let Some(codec) = &codec {
+                        chunk.compress(codec.as_ref(), compression_level)?;
                     }
-                    drop(wg);
-                    drop(s);
-                }
+                    chunk.serialize(format_version)
+                }))
+                .unwrap_or(Err(BdfError::WorkerFailed));
+
+                (sequence, info, result)
             });
-        }
     }
 
     /// Adds an entry to the hash lookup table
     /// If the lookup table has already been written to the file, an error is returned
-    pub fn add_lookup_entry(&mut self, mut entry: HashEntry) -> Result<u32, Error> {
+    pub fn add_lookup_entry(&mut self, entry: HashEntry) -> Result<u32, BdfError> {
         if self.head_written {
-            return Err(Error::new(
-                ErrorKind::Other,
-                "the head has already been written",
-            ));
+            return Err(BdfError::HeadAlreadyWritten);
         }
         let id = self.lookup_table.entries.len() as u32;
-        entry.id = id;
-        self.lookup_table.entries.insert(id, entry);
+        self.lookup_table.insert(id, entry);
 
         Ok(id)
     }
 
+    /// Stores a value in the `INFO` chunk that is written after the lookup table,
+    /// for example the source of the plaintexts or the tool that generated the file.
+    /// Returns an error if the head was already written.
+    pub fn set_info<V: Into<InfoValue>>(&mut self, key: &str, value: V) -> Result<(), BdfError> {
+        if self.head_written {
+            return Err(BdfError::HeadAlreadyWritten);
+        }
+        self.info.insert(key, value);
+
+        Ok(())
+    }
+
+    /// Adds a chunk of a custom type that is written after the lookup table.
+    /// Returns an error if the head was already written or if the id of the chunk
+    /// isn't four ASCII letters or belongs to a chunk of the format.
+    pub fn add_chunk(&mut self, chunk: &dyn Chunk) -> Result<(), BdfError> {
+        if self.head_written {
+            return Err(BdfError::HeadAlreadyWritten);
+        }
+        validate_chunk_id(chunk.id())?;
+        self.head_chunks.push(GenericChunk::from(chunk));
+
+        Ok(())
+    }
+
+    /// Returns the lookup table, for example to add hash values to data entries by name
+    pub fn lookup_table(&self) -> &HashLookupTable {
+        &self.lookup_table
+    }
+
     /// Adds a data entry to the file.
     /// If the number of entries per chunk is reached,
-    /// the data will be written to the file
-    pub fn add_data_entry(&mut self, data_entry: DataEntry) -> Result<(), Error> {
+    /// the data will be written to the file.
+    /// Returns an error if the file guarantees UTF-8 plaintexts and the plaintext isn't valid UTF-8
+    /// or if the entry is too large for the u32 length of a data row.
+    pub fn add_data_entry(&mut self, mut data_entry: DataEntry) -> Result<(), BdfError> {
+        if self.metadata.utf8 {
+            // the error keeps the rejected bytes
+            let plain = String::from_utf8(std::mem::take(&mut data_entry.plain))?;
+            data_entry.plain = plain.into_bytes();
+        }
+        let row_length = data_entry.serialized_len() as u64 - 4;
+        if row_length > u32::MAX as u64 {
+            return Err(BdfError::TooLarge {
+                what: "data entry",
+                size: row_length,
+                limit: u32::MAX as u64,
+            });
+        }
+        if let Some(sorter) = &mut self.sorter {
+            return sorter.push(data_entry, &self.lookup_table);
+        }
+        self.push_data_entry(data_entry)
+    }
+
+    /// Adds a data entry to the current chunk
+    fn push_data_entry(&mut self, data_entry: DataEntry) -> Result<(), BdfError> {
+        self.chunk_size += data_entry.serialized_len() as u64;
         self.data_entries.push(data_entry);
-        if self.data_entries.len() >= self.metadata.entries_per_chunk as usize {
+        let chunk_full = self
+            .bytes_per_chunk
+            .is_some_and(|bytes_per_chunk| self.chunk_size >= bytes_per_chunk);
+        if chunk_full || self.data_entries.len() >= self.metadata.entries_per_chunk as usize {
             self.flush()?;
         }
 
         Ok(())
     }
 
-    /// Writes the data to the file
-    fn flush(&mut self) -> Result<(), Error> {
+    /// Writes the header, the metadata and the lookup table if that didn't happen yet
+    fn write_head(&mut self) -> Result<(), BdfError> {
         if !self.head_written {
-            self.writer.write(BDF_HDR)?;
-            let mut generic_meta = GenericChunk::from(&self.metadata);
-            self.writer.write(generic_meta.serialize().as_slice())?;
-            let mut generic_lookup = GenericChunk::from(&self.lookup_table);
-            self.writer.write(generic_lookup.serialize().as_slice())?;
+            self.format_version = self.resolve_format_version()?;
+            self.write_bytes(&FileHeader::new(self.format_version).serialize())?;
+            self.metadata.info = !self.info.is_empty();
+            self.write_chunk(GenericChunk::from(&self.metadata))?;
+            self.write_chunk(GenericChunk::from(&self.lookup_table))?;
+            if self.metadata.info {
+                self.write_chunk(GenericChunk::from(&self.info))?;
+            }
+            for chunk in std::mem::take(&mut self.head_chunks) {
+                self.write_chunk(chunk)?;
+            }
             self.head_written = true;
         }
+
+        Ok(())
+    }
+
+    /// Returns the version the file is written in.
+    /// Files with chunks that readers of version 1 don't know are written in version 2,
+    /// and an explicitly requested version 1 is rejected for them. This includes the
+    /// trailing `META` chunk of streaming writers, which version 1 readers take for
+    /// a second file head, and custom critical chunks.
+    fn resolve_format_version(&self) -> Result<FormatVersion, BdfError> {
+        let extended = self.write_index
+            || self.write_stats
+            || self.sort_index.is_some()
+            || self.bloom_filter.is_some()
+            || !self.info.is_empty()
+            || matches!(self.meta_counts, MetaCounts::Trailer)
+            || self.head_chunks.iter().any(|chunk| chunk.id.is_critical());
+        match self.requested_version {
+            Some(FormatVersion::V1) if extended => Err(BdfError::InvalidConfig(
+                "index, sort, bloom, info, stat, trailing meta and custom critical chunks \
+                 require format version 2"
+                    .to_string(),
+            )),
+            Some(version) => Ok(version),
+            None if extended => Ok(FormatVersion::V2),
+            None => Ok(FormatVersion::V1),
+        }
+    }
+
+    /// Writes the data to the file
+    fn flush(&mut self) -> Result<(), BdfError> {
+        self.write_head()?;
         if !self.thread_manager.threads_started {
             self.start_threads();
-            self.thread_manager.threads_started = true;
         }
-        let data_chunk =
-            GenericChunk::from_data_entries(&self.data_entries, &self.lookup_table);
-        self.thread_manager.sender_work.send(data_chunk).expect("failed to send work to threads");
-        self.write_serialized()?;
+        let chunk = GenericChunk::from_data_entries(&self.data_entries, &self.lookup_table);
+        let info = ChunkInfo {
+            entry_count: self.data_entries.len() as u32,
+            key_range: self.key_range(),
+            bloom_filter: self.build_bloom_filter(),
+            stats: self
+                .write_stats
+                .then(|| ChunkStats::from_entries(&self.data_entries, chunk.length)),
+        };
+        // at most `max_in_flight` chunks are compressed or waiting to be written, so the
+        // workers never block on a full result channel and the reordering buffer is bounded
+        while self.next_sequence - self.next_write >= self.max_in_flight as u64 {
+            self.receive_chunk()?;
+        }
+        self.thread_manager
+            .send((self.next_sequence, chunk, info))?;
+        self.next_sequence += 1;
+        while let Ok(result) = self.thread_manager.receiver_result.try_recv() {
+            self.store_chunk(result)?;
+        }
         self.data_entries = Vec::new();
+        self.chunk_size = 0;
+
+        Ok(())
+    }
+
+    /// Waits for the next compressed chunk and writes all chunks that are ready
+    fn receive_chunk(&mut self) -> Result<(), BdfError> {
+        let result = self
+            .thread_manager
+            .receiver_result
+            .recv()
+            .map_err(|_| BdfError::WorkerFailed)?;
+
+        self.store_chunk(result)
+    }
+
+    /// Stores a compressed chunk and writes all chunks that follow the
+    /// last written chunk in the order they were added
+    fn store_chunk(&mut self, result: CompressedChunk) -> Result<(), BdfError> {
+        let (sequence, info, data) = result;
+        self.pending.insert(sequence, (info, data?));
+        while let Some((info, data)) = self.pending.remove(&self.next_write) {
+            self.write_data_chunk(info, &data)?;
+            self.next_write += 1;
+        }
 
         Ok(())
     }
 
-    fn write_serialized(&mut self) -> Result<(), Error> {
-        while let Ok(data) = self.thread_manager.receiver_result.try_recv() {
-            self.writer.write(data.as_slice())?;
+    /// Writes a serialized data chunk and adds it to the chunk index
+    fn write_data_chunk(&mut self, info: ChunkInfo, data: &[u8]) -> Result<(), BdfError> {
+        if let Some(stats) = &info.stats {
+            self.write_chunk(GenericChunk::from(stats))?;
         }
+        if let Some(bloom_filter) = &info.bloom_filter {
+            self.write_chunk(GenericChunk::from(bloom_filter))?;
+        }
+        let chunk_number = self.chunk_index.entries.len() as u64;
+        self.chunk_index.entries.push(ChunkIndexEntry {
+            offset: self.offset,
+            length: data.len() as u64 - self.format_version.chunk_overhead(),
+            entry_count: info.entry_count,
+        });
+        if let (Some(sort_index), Some((min, max))) = (&mut self.sort_index, info.key_range) {
+            sort_index.ranges.push(KeyRange {
+                chunk_number,
+                min,
+                max,
+            });
+        }
+        self.write_bytes(data)
+    }
+
+    /// Returns a bloom filter over the values of the filter hash in the current chunk
+    fn build_bloom_filter(&self) -> Option<BloomFilter> {
+        let (hash_name, false_positive_rate) = self.bloom_filter.as_ref()?;
+        let (hash_id, _) = self.lookup_table.get_entry(hash_name)?;
+        // the rate was validated when the filter was enabled
+        let mut filter =
+            BloomFilter::new(*hash_id, self.data_entries.len(), *false_positive_rate).ok()?;
+        for entry in &self.data_entries {
+            if let Some(value) = entry.hash(*hash_id) {
+                filter.insert(value);
+            }
+        }
+
+        Some(filter)
+    }
+
+    /// Returns the smallest and largest value of the sort hash in the current chunk
+    fn key_range(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let hash_id = self.sort_index.as_ref()?.hash_id;
+        let mut values = self
+            .data_entries
+            .iter()
+            .filter_map(|entry| entry.hash(hash_id));
+        let first = values.next()?;
+        let (min, max) = values.fold((first, first), |(min, max), value| {
+            (min.min(value), max.max(value))
+        });
+
+        Some((min.to_vec(), max.to_vec()))
+    }
+
+    /// Serializes the chunk in the format version of the file and writes it
+    fn write_chunk(&mut self, mut chunk: GenericChunk) -> Result<(), BdfError> {
+        chunk.crc = self.checksum.compute(&chunk.data);
+        let data = chunk.serialize(self.format_version)?;
+
+        self.write_bytes(&data)
+    }
+
+    /// Writes the bytes and keeps track of the position in the file
+    fn write_bytes(&mut self, data: &[u8]) -> Result<(), BdfError> {
+        self.writer.write_all(data)?;
+        self.offset += data.len() as u64;
 
         Ok(())
     }
 
+    /// Writes the chunk index followed by the footer pointing to it
+    fn write_chunk_index(&mut self) -> Result<(), BdfError> {
+        let footer = ChunkIndexFooter {
+            index_offset: self.offset,
+        };
+        let index_data = self.chunk_index.serialize(self.format_version)?;
+        self.write_chunk(GenericChunk::new(ChunkId::CIDX, index_data))?;
+        self.write_chunk(GenericChunk::from(&footer))
+    }
+
     /// Flushes the writer
     /// This should be called when no more data is being written
-    fn flush_writer(&mut self) -> Result<(), Error> {
-        self.writer.flush()
+    fn flush_writer(&mut self) -> Result<(), BdfError> {
+        self.writer.flush()?;
+
+        Ok(())
     }
 
     /// Flushes the buffered chunk data and the writer
     /// to finish the file.
-    pub fn finish(&mut self) -> Result<(), Error> {
-        self.flush()?;
+    /// Calling it again after the file was finished does nothing.
+    pub fn finish(&mut self) -> Result<(), BdfError> {
+        if self.finished {
+            return Ok(());
+        }
+        if let Some(sorter) = self.sorter.take() {
+            for entry in sorter.finish(&self.lookup_table)? {
+                self.push_data_entry(entry?)?;
+            }
+        }
+        if self.data_entries.is_empty() {
+            self.write_head()?;
+        } else {
+            self.flush()?;
+        }
         self.thread_manager.drop_sender();
-        self.thread_manager.wait();
-        self.write_serialized()?;
+        while self.next_write < self.next_sequence {
+            self.receive_chunk()?;
+        }
+        self.thread_manager.wait()?;
+        if let Some(sort_index) = &self.sort_index {
+            self.write_chunk(GenericChunk::from(sort_index))?;
+        }
+        if !matches!(self.meta_counts, MetaCounts::Fixed) {
+            self.metadata.chunk_count = self.chunk_index.entries.len() as u32;
+            self.metadata.entry_count = self.chunk_index.entry_count();
+        }
+        if let MetaCounts::Trailer = self.meta_counts {
+            self.write_chunk(GenericChunk::from(&self.metadata))?;
+        }
+        if self.write_index {
+            self.write_chunk_index()?;
+        }
         self.flush_writer()?;
+        if let MetaCounts::Patch(patch) = self.meta_counts {
+            let meta_distance = self.offset - HEADER_SIZE as u64;
+            let mut generic_meta = GenericChunk::from(&self.metadata);
+            generic_meta.crc = self.checksum.compute(&generic_meta.data);
+            let meta_data = generic_meta.serialize(self.format_version)?;
+            patch(self.writer.get_mut(), meta_distance, &meta_data)?;
+        }
+        self.finished = true;
 
         Ok(())
     }
 
-    /// Sets the compression level for lzma compression
+    /// Returns the inner writer.
+    /// This should only be called after the file was finished.
+    pub fn into_inner(self) -> Result<W, BdfError> {
+        self.writer
+            .into_inner()
+            .map_err(|err| BdfError::Io(err.into_error()))
+    }
+
+    /// Sets the compression level that is passed to the codec
     pub fn set_compression_level(&mut self, level: u32) {
         self.compression_level = level;
     }
 
-    /// Changes the entries per chunk value.
-    /// Returns an error if the metadata has already been written.
-    pub fn set_entries_per_chunk(&mut self, number: u32) -> Result<(), Error> {
+    /// Compresses the data chunks with the built-in codec of the given name
+    /// or disables compression if `None` is passed.
+    /// Returns an error if the codec isn't available or the metadata has already been written.
+    pub fn set_compression_method(&mut self, method: Option<&str>) -> Result<(), BdfError> {
+        let codec = match method {
+            Some(name) => Some(
+                CodecRegistry::default()
+                    .get(name)
+                    .ok_or_else(|| BdfError::UnsupportedCompression(name.to_string()))?,
+            ),
+            None => None,
+        };
+
+        self.set_codec(codec)
+    }
+
+    /// Compresses the data chunks with the given codec or disables compression if `None` is passed.
+    /// Readers have to register the same codec to read the file.
+    /// Returns an error if the name of the codec isn't four bytes long
+    /// or the metadata has already been written.
+    pub fn set_codec(&mut self, codec: Option<Arc<dyn Codec>>) -> Result<(), BdfError> {
         if self.head_written {
-            return Err(Error::new(
-                ErrorKind::Other,
-                "the head has already been written",
-            ));
+            return Err(BdfError::HeadAlreadyWritten);
+        }
+        if let Some(codec) = &codec {
+            validate_codec_name(codec.name())?;
         }
-        self.metadata.entries_per_chunk = number;
-        self.metadata.chunk_count =
-            (self.metadata.entry_count as f64 / number as f64).ceil() as u32;
+        self.metadata.compression_method = codec.as_ref().map(|codec| codec.name().to_string());
+        self.codec = codec;
+
         Ok(())
     }
-}
 
-impl BDFReader {
-    /// Creates a new BDFReader
-    pub fn new(inner: File) -> Self {
-        Self {
-            metadata: None,
-            lookup_table: None,
-            reader: BufReader::new(inner),
-            compressed: false,
-            thread_manager: ThreadManager::new(num_cpus::get() * 2),
+    /// Sets the number of worker threads that compress chunks.
+    /// It is also the maximum number of chunks that are compressed or
+    /// waiting to be written at the same time.
+    /// Returns an error if the workers were already started.
+    pub fn set_threads(&mut self, threads: usize) -> Result<(), BdfError> {
+        if self.thread_manager.threads_started {
+            return Err(BdfError::WorkersStarted);
         }
+        self.max_in_flight = threads.max(1);
+
+        Ok(())
     }
 
-    /// Reads the metadata and lookup table
-    pub fn read_start(&mut self) -> Result<(), Error> {
-        self.read_metadata()?;
-        self.read_lookup_table()?;
+    /// Compresses the chunks on the executor instead of private worker threads.
+    /// Returns an error if the workers were already started.
+    pub fn set_executor(&mut self, executor: Arc<dyn Executor>) -> Result<(), BdfError> {
+        if self.thread_manager.threads_started {
+            return Err(BdfError::WorkersStarted);
+        }
+        self.executor = Some(executor);
 
         Ok(())
     }
 
-    /// Starts threads for decompressing chunks
-    fn start_threads(&mut self) {
-        for _ in 0..(num_cpus::get() as f32/2f32).max(1f32) as usize {
-            thread::spawn({
-                let r = self.thread_manager.receiver_work.clone();
-                let s = self.thread_manager.sender_result.clone();
-                let wg = self.thread_manager.wg.clone();
-                move || {
-                    for mut chunk in r {
-                        chunk.decompress().expect("failed to decompress chunk");
-                        s.send(chunk).expect("failed to send decompression result");
-                    }
-                    drop(wg);
-                }
-            });
+    /// Enables writing a `CIDX` chunk with the position of every data chunk
+    /// at the end of the file. Readers use it for random access to chunks and entries.
+    /// Returns an error if the head has already been written.
+    pub fn set_write_index(&mut self, write_index: bool) -> Result<(), BdfError> {
+        if self.head_written {
+            return Err(BdfError::HeadAlreadyWritten);
         }
-        // add some initial data to be decompressed.
-        // the data that is added is four times the number of threads
-        for _ in 0..num_cpus::get() * 2 {
-            if let Err(_) = self.add_compression_chunk() {
-                self.thread_manager.drop_sender();
-                break;
-            }
+        self.write_index = write_index;
+
+        Ok(())
+    }
+
+    /// Enables writing a `stat` chunk with the entry count, the uncompressed size and
+    /// the ranges of the plaintext lengths and hash values before each data chunk.
+    /// Readers use them to skip data chunks without decompressing them.
+    /// Returns an error if the head has already been written.
+    pub fn set_write_stats(&mut self, write_stats: bool) -> Result<(), BdfError> {
+        if self.head_written {
+            return Err(BdfError::HeadAlreadyWritten);
         }
-        self.thread_manager.drop_sender_result();
+        self.write_stats = write_stats;
+
+        Ok(())
     }
 
-    /// Adds a chunk to the decompression channel to be decompressed by a worker thread
-    pub fn add_compression_chunk(&mut self) -> Result<(), Error> {
-        let gen_chunk = self.next_chunk_raw()?;
-        if gen_chunk.name == DTBL_CHUNK_NAME.to_string() && self.compressed {
-            if let Err(_) = self.thread_manager.sender_work.send(gen_chunk) {
-                return Err(Error::new(ErrorKind::Other, "failed to send chunk data"))
-            }
+    /// Sorts all data entries by the value of the hash function `hash_name`
+    /// and stores the range of values of each chunk in a `SORT` chunk.
+    /// Readers can then find a hash by only decompressing the chunk that can contain it.
+    /// This also enables the chunk index, which the readers need to find the chunks.
+    /// Entries that don't fit into the sort buffer are sorted on disk.
+    /// Returns an error if data was already written or the hash function isn't
+    /// in the lookup table.
+    pub fn set_sort_by(&mut self, hash_name: &str) -> Result<(), BdfError> {
+        if self.head_written {
+            return Err(BdfError::HeadAlreadyWritten);
         }
+        let (hash_id, _) = self
+            .lookup_table
+            .get_entry(hash_name)
+            .ok_or_else(|| BdfError::UnknownHashName(hash_name.to_string()))?;
+        self.sort_index = Some(SortIndex {
+            hash_id: *hash_id,
+            ranges: Vec::new(),
+        });
+        self.write_index = true;
+        let mut sorter =
+            ExternalSorter::new(*hash_id, hash_name.to_string(), self.sort_buffer_size);
+        for entry in std::mem::take(&mut self.data_entries) {
+            sorter.push(entry, &self.lookup_table)?;
+        }
+        self.sorter = Some(sorter);
 
         Ok(())
     }
 
-    /// Verifies the header of the file and reads and stores the metadata
-    pub fn read_metadata(&mut self) -> Result<&MetaChunk, Error> {
-        if !self.validate_header() {
-            return Err(Error::new(ErrorKind::InvalidData, "invalid BDF Header"));
+    /// Writes a `BLOM` chunk with a bloom filter over the values of the hash function
+    /// `hash_name` before each data chunk. Lookups skip data chunks whose
+    /// filter rules out every target hash without decompressing them.
+    /// Returns an error if the head has already been written, the hash function
+    /// isn't in the lookup table or the rate isn't strictly between 0 and 1.
+    pub fn set_bloom_filter(
+        &mut self,
+        hash_name: &str,
+        false_positive_rate: f64,
+    ) -> Result<(), BdfError> {
+        if self.head_written {
+            return Err(BdfError::HeadAlreadyWritten);
         }
-        let meta_chunk: MetaChunk = self.next_chunk_raw()?.try_into()?;
-        if let Some(method) = &meta_chunk.compression_method {
-            if *method == LZMA.to_string() {
-                self.compressed = true;
-            } else {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    "unsupported compression method",
-                ));
-            }
+        if self.lookup_table.get_entry(hash_name).is_none() {
+            return Err(BdfError::UnknownHashName(hash_name.to_string()));
         }
-        self.metadata = Some(meta_chunk);
+        validate_false_positive_rate(false_positive_rate)?;
+        self.bloom_filter = Some((hash_name.to_string(), false_positive_rate));
 
-        if let Some(chunk) = &self.metadata {
-            Ok(&chunk)
-        } else {
-            Err(Error::new(
-                ErrorKind::Other,
-                "Failed to read self assigned metadata.",
-            ))
+        Ok(())
+    }
+
+    /// Sets the maximum number of entries that are sorted in memory
+    /// before they are written to a temporary file
+    pub fn set_sort_buffer_size(&mut self, entries: usize) {
+        self.sort_buffer_size = entries;
+        if let Some(sorter) = &mut self.sorter {
+            sorter.set_buffer_size(entries);
         }
     }
 
-    /// Reads the lookup table of the file.
-    /// This function should be called after the read_metadata function was called
-    pub fn read_lookup_table(&mut self) -> Result<&HashLookupTable, Error> {
-        match &self.metadata {
-            None => self.read_metadata()?,
-            Some(t) => t,
-        };
-        let lookup_table: HashLookupTable = self.next_chunk_raw()?.try_into()?;
-        self.lookup_table = Some(lookup_table);
+    /// Changes the entries per chunk value.
+    /// Returns an error if the metadata has already been written.
+    pub fn set_entries_per_chunk(&mut self, number: u32) -> Result<(), BdfError> {
+        if self.head_written {
+            return Err(BdfError::HeadAlreadyWritten);
+        }
+        self.metadata.set_entries_per_chunk(number);
+        Ok(())
+    }
 
-        if self.compressed {
-            self.start_threads();
+    /// Sets the version of the format the file is written in.
+    /// Without it files are written in version 1 unless they contain index, sort,
+    /// bloom filter, info or stat chunks, which require version 2.
+    /// Returns an error if the header has already been written.
+    pub fn set_format_version(&mut self, version: FormatVersion) -> Result<(), BdfError> {
+        if self.head_written {
+            return Err(BdfError::HeadAlreadyWritten);
         }
+        self.requested_version = Some(version);
 
-        if let Some(chunk) = &self.lookup_table {
-            Ok(&chunk)
-        } else {
-            Err(Error::new(
-                ErrorKind::Other,
-                "failed to read self assigned chunk",
-            ))
+        Ok(())
+    }
+
+    /// Sets the checksum stored at the end of every chunk.
+    /// Returns an error if the head has already been written.
+    pub fn set_checksum(&mut self, checksum: Checksum) -> Result<(), BdfError> {
+        if self.head_written {
+            return Err(BdfError::HeadAlreadyWritten);
         }
+        self.checksum = checksum;
+
+        Ok(())
     }
 
-    /// Validates the header of the file
-    fn validate_header(&mut self) -> bool {
-        let mut header = [0u8; 11];
-        let _ = self.reader.read(&mut header);
+    /// Declares in the metadata that every plaintext is valid UTF-8.
+    /// Data entries with other plaintexts are rejected afterwards.
+    /// Returns an error if the metadata has already been written.
+    pub fn set_utf8(&mut self, utf8: bool) -> Result<(), BdfError> {
+        if self.head_written {
+            return Err(BdfError::HeadAlreadyWritten);
+        }
+        self.metadata.utf8 = utf8;
 
-        header == BDF_HDR.as_ref()
+        Ok(())
     }
+}
 
-    /// Returns the next chunk
-    pub fn next_chunk(&mut self) -> Result<GenericChunk, Error> {
-        if self.compressed {
-            if let Err(_) = self.add_compression_chunk() {
-                self.thread_manager.drop_sender();
-            }
-            if let Ok(chunk) = self.thread_manager.receiver_result.recv() {
-                Ok(chunk)
-            } else {
-                Err(Error::new(ErrorKind::Other, "failed to get chunk"))
-            }
-        } else {
-            self.next_chunk_raw()
+impl<W: Write> BDFWriter<W> {
+    /// Starts a new data chunk as soon as the serialized entries of the current one
+    /// reach the given number of bytes, even if it has fewer entries than the
+    /// entries per chunk value.
+    /// Returns an error if the metadata has already been written or the number of chunks
+    /// has to be known up front because the writer was created with [`BDFWriter::new`].
+    pub fn set_bytes_per_chunk(&mut self, bytes: u64) -> Result<(), BdfError> {
+        if self.head_written {
+            return Err(BdfError::HeadAlreadyWritten);
         }
+        if let MetaCounts::Fixed = self.meta_counts {
+            return Err(BdfError::InvalidConfig(
+                "bytes per chunk require a seekable or streaming writer".to_string(),
+            ));
+        }
+        if bytes == 0 || bytes > u32::MAX as u64 {
+            return Err(BdfError::InvalidConfig(format!(
+                "bytes per chunk must be between 1 and {}",
+                u32::MAX
+            )));
+        }
+        self.bytes_per_chunk = Some(bytes);
+
+        Ok(())
     }
+}
 
-    /// Returns the next chunk if one is available.
-    fn next_chunk_raw(&mut self) -> Result<GenericChunk, Error> {
-        let mut length_raw = [0u8; 4];
-        let _ = self.reader.read_exact(&mut length_raw)?;
-        let length = BigEndian::read_u32(&mut length_raw);
-        let mut name_raw = [0u8; 4];
-        let _ = self.reader.read_exact(&mut name_raw)?;
-        let name = String::from_utf8(name_raw.to_vec()).expect("Failed to parse name string.");
-        let mut data = vec![0u8; length as usize];
-        let _ = self.reader.read_exact(&mut data)?;
-        let mut crc_raw = [0u8; 4];
-        let _ = self.reader.read_exact(&mut crc_raw)?;
-        let crc = BigEndian::read_u32(&mut crc_raw);
+impl<W: Write + Seek> BDFWriter<W> {
+    /// Creates a new BDFWriter when the number of entries isn't known in advance.
+    /// [`BDFWriter::finish`] goes back to the start of the file and
+    /// overwrites the `META` chunk with the number of entries and chunks that were written.
+    pub fn new_seekable(inner: W, compress: bool) -> Self {
+        Self::with_metadata(
+            inner,
+            MetaChunk::with_unknown_counts(ENTRIES_PER_CHUNK, compress),
+            MetaCounts::Patch(patch_metadata::<W>),
+        )
+    }
+}
 
-        Ok(GenericChunk {
-            length,
-            name,
-            data,
-            crc,
-        })
+/// Moves the buffered reader to the given offset
+fn seek_reader<R: Read + Seek>(reader: &mut BufReader<R>, offset: u64) -> std::io::Result<()> {
+    reader.seek(SeekFrom::Start(offset)).map(|_| ())
+}
+
+/// Overwrites the `META` chunk that starts `distance` bytes before the end of the written data
+fn patch_metadata<W: Write + Seek>(
+    writer: &mut W,
+    distance: u64,
+    meta_chunk: &[u8],
+) -> std::io::Result<()> {
+    let end = writer.stream_position()?;
+    writer.seek(SeekFrom::Start(end - distance))?;
+    writer.write_all(meta_chunk)?;
+    writer.seek(SeekFrom::Start(end))?;
+
+    writer.flush()
+}
+
+impl BDFReader<File> {
+    /// Opens the file at the given path and returns a BDFReader for it
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, BdfError> {
+        Ok(Self::new_seekable(File::open(path)?))
+    }
+}
+
+impl<R: Read> BDFReader<R> {
+    /// Creates a new BDFReader
+    pub fn new(inner: R) -> Self {
+        let threads = num_cpus::get();
+        Self {
+            metadata: None,
+            lookup_table: None,
+            info: None,
+            reader: BufReader::new(inner),
+            codec: None,
+            codecs: CodecRegistry::default(),
+            chunk_types: ChunkRegistry::new(),
+            chunk_index: 0,
+            thread_manager: ThreadManager::new(0, None),
+            threads: (threads / 2).max(1),
+            executor: None,
+            pending: BTreeMap::new(),
+            next_index: 0,
+            eof: false,
+            prefetch: threads * 2,
+            offset: 0,
+            data_start: 0,
+            data_start_chunk: 0,
+            seek: None,
+            index: None,
+            data_chunks_read: 0,
+            query: None,
+            query_matches: None,
+            skip_next_data: false,
+            skipped_chunks: HashSet::new(),
+            stats_filter: None,
+            limits: ReadLimits::default(),
+            validation: Validation::default(),
+            checksum: Checksum::default(),
+            format_version: None,
+        }
+    }
+
+    /// Changes the limits for sizes and counts read from the file.
+    /// The limits have to be set before any chunk is read.
+    pub fn set_limits(&mut self, limits: ReadLimits) {
+        self.limits = limits;
+    }
+
+    /// Changes how strictly the chunks are checked
+    pub fn set_validation(&mut self, validation: Validation) {
+        self.validation = validation;
+    }
+
+    /// Sets the checksum the chunks of the file are expected to have
+    pub fn set_checksum(&mut self, checksum: Checksum) {
+        self.checksum = checksum;
+    }
+
+    /// Skips the data chunks whose `stat` chunk doesn't match the filter.
+    /// Data chunks without statistics are always read.
+    pub fn set_stats_filter(&mut self, filter: Option<StatsFilter>) {
+        self.stats_filter = filter;
+    }
+
+    /// Reads the metadata and lookup table
+    pub fn read_start(&mut self) -> Result<(), BdfError> {
+        self.read_metadata()?;
+        self.read_lookup_table()?;
+
+        Ok(())
+    }
+
+    /// Sets the number of worker threads that decompress chunks.
+    /// Returns an error if the workers were already started.
+    pub fn set_threads(&mut self, threads: usize) -> Result<(), BdfError> {
+        if self.thread_manager.threads_started {
+            return Err(BdfError::WorkersStarted);
+        }
+        self.threads = threads.max(1);
+
+        Ok(())
+    }
+
+    /// Sets the maximum number of chunks that are read ahead and decompressed
+    /// before they are returned.
+    /// Returns an error if the workers were already started.
+    pub fn set_prefetch(&mut self, prefetch: usize) -> Result<(), BdfError> {
+        if self.thread_manager.threads_started {
+            return Err(BdfError::WorkersStarted);
+        }
+        self.prefetch = prefetch.max(1);
+
+        Ok(())
+    }
+
+    /// Decompresses the chunks on the executor instead of private worker threads.
+    /// Returns an error if the workers were already started.
+    pub fn set_executor(&mut self, executor: Arc<dyn Executor>) -> Result<(), BdfError> {
+        if self.thread_manager.threads_started {
+            return Err(BdfError::WorkersStarted);
+        }
+        self.executor = Some(executor);
+
+        Ok(())
+    }
+
+    /// Adds a codec that can be used to decompress files.
+    /// Codecs have to be registered before the metadata is read.
+    /// Returns an error if the name of the codec isn't four bytes long.
+    pub fn register_codec(&mut self, codec: Arc<dyn Codec>) -> Result<(), BdfError> {
+        self.codecs.register(codec)
+    }
+
+    /// Adds a decoder for a custom chunk type.
+    /// Critical chunks of unknown types make reading fail, while unknown ancillary
+    /// chunks are skipped by [`BDFReader::next_chunk`].
+    /// Returns an error if the id isn't four ASCII letters or belongs to a chunk of the format.
+    pub fn register_chunk_decoder(
+        &mut self,
+        decoder: Arc<dyn ChunkDecoder>,
+    ) -> Result<(), BdfError> {
+        self.chunk_types.register(decoder)
+    }
+
+    /// Decodes a chunk of a custom type with its registered decoder.
+    /// Returns `None` if no decoder is registered for the type of the chunk.
+    pub fn decode_chunk(&self, chunk: &GenericChunk) -> Result<Option<Box<dyn Chunk>>, BdfError> {
+        self.chunk_types.decode(chunk)
+    }
+
+    /// Starts threads for decompressing chunks
+    fn start_threads(&mut self) {
+        let codec = self.codec.clone();
+        let max_size = self.limits.max_chunk_size;
+        self.thread_manager = ThreadManager::new(self.prefetch, self.executor.clone());
+        self.thread_manager
+            .start(self.threads, move |(mut chunk, query): DecompressionJob| {
+                let index = chunk.index;
+                let result = match &codec {
+                    Some(codec) if chunk.id == ChunkId::DTBL => {
+                        panic::catch_unwind(AssertUnwindSafe(|| {
+                            chunk.decompress_limited(codec.as_ref(), max_size)
+                        }))
+                        .unwrap_or(Err(BdfError::WorkerFailed))
+                        .map(|_| chunk)
+                    }
+                    _ => Ok(chunk),
+                };
+                let matches = match (&result, query) {
+                    (Ok(chunk), Some(query)) if chunk.id == ChunkId::DTBL => {
+                        Some(chunk.find_entries(&query.lookup_table, query.hash_id, &query.targets))
+                    }
+                    _ => None,
+                };
+
+                (index, result, matches)
+            });
+        self.next_index = self.chunk_index;
+    }
+
+    /// Reads chunks and hands them to the worker threads until
+    /// the prefetch limit or the end of the file is reached
+    fn fill_pipeline(&mut self) {
+        while !self.eof && self.pending.len() < self.prefetch {
+            let index = self.chunk_index;
+            match self.next_chunk_raw() {
+                Ok(Some(chunk)) if self.skipped_chunks.contains(&index) => {
+                    self.pending.insert(index, Some((Ok(chunk), None)));
+                }
+                Ok(Some(chunk)) => {
+                    if let Err(err) = self.thread_manager.send((chunk, self.query.clone())) {
+                        self.finish_pipeline(index, Err(err));
+                    } else {
+                        self.pending.insert(index, None);
+                    }
+                }
+                Ok(None) => self.finish_pipeline(index, Ok(())),
+                Err(err) => self.finish_pipeline(index, Err(err)),
+            }
+        }
+    }
+
+    /// Stops reading further chunks. An error is stored
+    /// so that it is returned after all chunks before it.
+    fn finish_pipeline(&mut self, index: u64, result: Result<(), BdfError>) {
+        self.eof = true;
+        self.thread_manager.drop_sender();
+        if let Err(err) = result {
+            self.pending.insert(index, Some((Err(err), None)));
+        }
+    }
+
+    /// Verifies the header of the file and reads and stores the metadata
+    pub fn read_metadata(&mut self) -> Result<&MetaChunk, BdfError> {
+        self.read_header()?;
+        let meta_chunk = self.next_chunk_raw()?.ok_or(BdfError::Truncated)?;
+        self.check_crc(&meta_chunk)?;
+        let meta_chunk: MetaChunk = meta_chunk.try_into()?;
+        if let Some(method) = &meta_chunk.compression_method {
+            let codec = self
+                .codecs
+                .get(method)
+                .ok_or_else(|| BdfError::UnsupportedCompression(method.clone()))?;
+            self.codec = Some(codec);
+        }
+
+        Ok(self.metadata.get_or_insert(meta_chunk))
+    }
+
+    /// Reads the lookup table of the file.
+    /// This function should be called after the read_metadata function was called
+    pub fn read_lookup_table(&mut self) -> Result<&HashLookupTable, BdfError> {
+        if self.metadata.is_none() {
+            self.read_metadata()?;
+        }
+        let lookup_table = self.next_chunk_raw()?.ok_or(BdfError::Truncated)?;
+        self.check_crc(&lookup_table)?;
+        let lookup_table =
+            HashLookupTable::parse_limited(lookup_table, self.limits.max_hash_count)?;
+        if self.metadata.as_ref().is_some_and(|meta| meta.info) {
+            let info = self.next_chunk_raw()?.ok_or(BdfError::Truncated)?;
+            self.check_crc(&info)?;
+            self.info = Some(info.try_into()?);
+        }
+        self.data_start = self.offset;
+        self.data_start_chunk = self.chunk_index;
+
+        Ok(self.lookup_table.insert(lookup_table))
+    }
+
+    /// Returns the `INFO` chunk of the file or `None` if it doesn't have one.
+    /// The metadata and lookup table are read first if that didn't happen yet.
+    pub fn info(&mut self) -> Result<Option<&InfoChunk>, BdfError> {
+        self.ensure_start()?;
+
+        Ok(self.info.as_ref())
+    }
+
+    /// Checks the crc sum of a chunk that isn't decompressed if the validation is strict
+    fn check_crc(&self, chunk: &GenericChunk) -> Result<(), BdfError> {
+        match self.validation {
+            Validation::Strict => chunk.verify_crc(),
+            Validation::Lenient => Ok(()),
+        }
+    }
+
+    /// Reads and validates the header of the file and returns its format version
+    fn read_header(&mut self) -> Result<FormatVersion, BdfError> {
+        let mut header = [0u8; HEADER_SIZE];
+        self.reader.read_exact(&mut header).map_err(|err| {
+            if err.kind() == ErrorKind::UnexpectedEof {
+                BdfError::Truncated
+            } else {
+                BdfError::Io(err)
+            }
+        })?;
+        self.offset += HEADER_SIZE as u64;
+        let version = FileHeader::parse(&header).format_version()?;
+        self.format_version = Some(version);
+
+        Ok(version)
+    }
+
+    /// Returns the format version of the file.
+    /// The metadata is read first if that didn't happen yet.
+    pub fn format_version(&mut self) -> Result<FormatVersion, BdfError> {
+        match self.format_version {
+            Some(version) => Ok(version),
+            None => {
+                self.read_metadata()?;
+                Ok(self.format_version.unwrap_or_default())
+            }
+        }
+    }
+
+    /// Returns the next chunk or `None` if the end of the file was reached.
+    /// Data chunks of compressed files are decompressed by the worker threads
+    /// but still returned in the order they are stored in.
+    /// A trailing `META` chunk replaces the metadata read at the start of the file.
+    /// Ancillary chunks of unknown types are skipped, critical ones return an error.
+    pub fn next_chunk(&mut self) -> Result<Option<GenericChunk>, BdfError> {
+        loop {
+            let chunk = self.next_chunk_ordered()?;
+            if let Some(chunk) = &chunk {
+                if !self.chunk_types.is_known(chunk.id) {
+                    if chunk.id.is_ancillary() {
+                        continue;
+                    }
+                    return Err(BdfError::UnknownChunk(chunk.id));
+                }
+                if self.codec.is_none() || chunk.id != ChunkId::DTBL {
+                    self.check_crc(chunk)?;
+                }
+                if chunk.id == ChunkId::META {
+                    self.metadata = Some(MetaChunk::try_from(chunk.clone())?);
+                } else if chunk.id == ChunkId::DTBL {
+                    self.data_chunks_read += 1;
+                    if self.skipped_chunks.remove(&chunk.index) {
+                        continue;
+                    }
+                }
+            }
+
+            return Ok(chunk);
+        }
+    }
+
+    /// Returns the next chunk from the worker threads or directly from the file
+    fn next_chunk_ordered(&mut self) -> Result<Option<GenericChunk>, BdfError> {
+        self.query_matches = None;
+        if self.codec.is_none() {
+            return self.next_chunk_raw();
+        }
+        if !self.thread_manager.threads_started {
+            self.start_threads();
+        }
+        self.fill_pipeline();
+
+        loop {
+            match self.pending.get(&self.next_index) {
+                None => return Ok(None),
+                Some(Some(_)) => {
+                    let (result, matches) = self
+                        .pending
+                        .remove(&self.next_index)
+                        .flatten()
+                        .ok_or(BdfError::WorkerFailed)?;
+                    self.next_index += 1;
+                    self.query_matches = matches;
+                    return result.map(Some);
+                }
+                Some(None) => {
+                    let (index, result, matches) = self
+                        .thread_manager
+                        .receiver_result
+                        .recv()
+                        .map_err(|_| BdfError::WorkerFailed)?;
+                    self.pending.insert(index, Some((result, matches)));
+                }
+            }
+        }
+    }
+
+    /// Returns an iterator over all chunks following the lookup table.
+    /// The metadata and lookup table are read first if that didn't happen yet.
+    pub fn chunks(&mut self) -> Chunks<'_, R> {
+        Chunks {
+            reader: self,
+            finished: false,
+        }
+    }
+
+    /// Returns an iterator over all data entries of the file.
+    /// The metadata and lookup table are read first if that didn't happen yet.
+    pub fn entries(&mut self) -> Entries<'_, R> {
+        Entries {
+            chunks: self.chunks(),
+            lookup_table: None,
+            current: Vec::new().into_iter(),
+        }
+    }
+
+    /// Searches the remaining data chunks for entries with the given value
+    /// for the hash function `hash_name`.
+    /// Compressed chunks are decompressed and searched in parallel by the worker threads.
+    /// Readers created with [`BDFReader::new_seekable`] go back to the first data chunk
+    /// afterwards, other readers are at the end of the file.
+    pub fn find_by_hash(
+        &mut self,
+        hash_name: &str,
+        hash: &[u8],
+    ) -> Result<Vec<HashMatch>, BdfError> {
+        let mut targets = HashSet::new();
+        targets.insert(hash.to_vec());
+
+        self.find_by_hashes(hash_name, &targets)
+    }
+
+    /// Searches the remaining data chunks for entries whose value for the hash
+    /// function `hash_name` is one of the target hashes.
+    /// The matches are returned in the order they are stored in the file.
+    /// This consumes the stream of readers that weren't created with
+    /// [`BDFReader::new_seekable`], seekable readers go back to the first data chunk.
+    pub fn find_by_hashes(
+        &mut self,
+        hash_name: &str,
+        targets: &HashSet<Vec<u8>>,
+    ) -> Result<Vec<HashMatch>, BdfError> {
+        let lookup_table = self.ensure_start()?.clone();
+        let hash_id = *lookup_table
+            .get_entry(hash_name)
+            .ok_or_else(|| BdfError::UnknownHashName(hash_name.to_string()))?
+            .0;
+        let query = Arc::new(HashQuery {
+            lookup_table,
+            hash_id,
+            targets: targets.clone(),
+        });
+        self.query = Some(Arc::clone(&query));
+        let result = self.collect_matches(&query);
+        self.query = None;
+        self.rewind()?;
+
+        result
+    }
+
+    /// Reads all remaining data chunks and returns the matching entries.
+    /// Chunks that were decompressed by the worker threads were already searched by them.
+    fn collect_matches(&mut self, query: &HashQuery) -> Result<Vec<HashMatch>, BdfError> {
+        let mut matches = Vec::new();
+
+        while let Some(chunk) = self.next_chunk()? {
+            if chunk.id != ChunkId::DTBL {
+                continue;
+            }
+            let chunk_number = self.data_chunks_read - 1;
+            let found = match self.query_matches.take() {
+                Some(found) => found?,
+                None => chunk.find_entries(&query.lookup_table, query.hash_id, &query.targets)?,
+            };
+            for (entry_number, entry) in found {
+                matches.push(HashMatch {
+                    chunk_number,
+                    entry_number,
+                    entry,
+                });
+            }
+        }
+
+        Ok(matches)
+    }
+
+    /// Reads the whole file and checks the crc sum of every chunk.
+    /// It also checks that every data row only references hash functions of the lookup
+    /// table, that the hash values have the output lengths stored in it and that
+    /// the counts in the metadata match the data chunks.
+    /// The file is read from the start, so this has to be called on a new reader.
+    /// Problems with the file are collected in the report,
+    /// only errors of the inner reader are returned.
+    pub fn verify(&mut self) -> Result<VerifyReport, BdfError> {
+        let mut report = VerifyReport::default();
+        match self.read_header() {
+            Ok(_) => {}
+            Err(BdfError::Io(err)) => return Err(BdfError::Io(err)),
+            Err(BdfError::UnsupportedVersion(version)) => {
+                report.problems.push(Problem::UnsupportedVersion(version));
+                return Ok(report);
+            }
+            Err(_) => {
+                report.problems.push(Problem::InvalidHeader);
+                return Ok(report);
+            }
+        }
+        let mut data_readable = true;
+
+        loop {
+            let offset = self.offset;
+            let mut chunk = match self.next_chunk_raw() {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(BdfError::Io(err)) => return Err(BdfError::Io(err)),
+                Err(BdfError::Truncated) => {
+                    report.problems.push(Problem::Truncated { offset });
+                    break;
+                }
+                Err(error) => {
+                    report.problems.push(Problem::InvalidChunk {
+                        chunk_index: self.chunk_index,
+                        name: String::new(),
+                        error,
+                    });
+                    break;
+                }
+            };
+            if chunk.id == ChunkId::DTBL && !data_readable {
+                report.data_chunks += 1;
+                continue;
+            }
+            report.chunks_checked += 1;
+            let expected_id = match chunk.index {
+                0 => Some(ChunkId::META),
+                1 => Some(ChunkId::HTBL),
+                2 if self.metadata.as_ref().is_some_and(|meta| meta.info) => Some(ChunkId::INFO),
+                _ => None,
+            };
+            if let Some(expected) = expected_id.filter(|id| chunk.id != *id) {
+                report.problems.push(Problem::UnexpectedChunk {
+                    chunk_index: chunk.index,
+                    expected: expected.to_string(),
+                    found: chunk.id.to_string(),
+                });
+            }
+            let crc_valid = match self.verify_crc(&mut chunk) {
+                Ok(()) => true,
+                Err(problem) => {
+                    report.problems.push(problem);
+                    false
+                }
+            };
+            let chunk_index = chunk.index;
+            let name = chunk.id.to_string();
+            let result = match chunk.id {
+                ChunkId::META => MetaChunk::try_from(chunk).and_then(|metadata| {
+                    let method = metadata.compression_method.clone();
+                    self.metadata = Some(metadata);
+                    match method {
+                        Some(method) if chunk_index == 0 => {
+                            self.codec = self.codecs.get(&method);
+                            data_readable = self.codec.is_some();
+                            if data_readable {
+                                Ok(())
+                            } else {
+                                Err(BdfError::UnsupportedCompression(method))
+                            }
+                        }
+                        _ => Ok(()),
+                    }
+                }),
+                ChunkId::HTBL => HashLookupTable::parse_limited(chunk, self.limits.max_hash_count)
+                    .map(|lookup_table| {
+                        self.lookup_table = Some(lookup_table);
+                    }),
+                ChunkId::INFO => InfoChunk::try_from(chunk).map(|info| {
+                    self.info = Some(info);
+                }),
+                ChunkId::STAT => ChunkStats::try_from(chunk).map(|_| ()),
+                ChunkId::DTBL => {
+                    report.data_chunks += 1;
+                    let utf8 = self.metadata.as_ref().is_some_and(|meta| meta.utf8);
+                    match &self.lookup_table {
+                        Some(lookup_table) if crc_valid || self.codec.is_none() => chunk
+                            .verify_entries(lookup_table, utf8)
+                            .map(|(count, problems)| {
+                                report.entries += count as u64;
+                                report.problems.extend(problems);
+                            }),
+                        _ => Ok(()),
+                    }
+                }
+                id if !self.chunk_types.is_known(id) => {
+                    if id.is_ancillary() {
+                        Ok(())
+                    } else {
+                        Err(BdfError::UnknownChunk(id))
+                    }
+                }
+                _ => self.chunk_types.decode(&chunk).map(|_| ()),
+            };
+            if let Err(error) = result {
+                report.problems.push(Problem::InvalidChunk {
+                    chunk_index,
+                    name,
+                    error,
+                });
+            }
+        }
+        self.eof = true;
+
+        if let Some(metadata) = self.metadata.as_ref().filter(|meta| meta.counts_known()) {
+            if metadata.chunk_count as u64 != report.data_chunks {
+                report.problems.push(Problem::ChunkCountMismatch {
+                    expected: metadata.chunk_count,
+                    actual: report.data_chunks,
+                });
+            }
+            if metadata.entry_count != report.entries {
+                report.problems.push(Problem::EntryCountMismatch {
+                    expected: metadata.entry_count,
+                    actual: report.entries,
+                });
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Checks the crc sum of the chunk.
+    /// Data chunks of compressed files are decompressed to check it.
+    fn verify_crc(&self, chunk: &mut GenericChunk) -> Result<(), Problem> {
+        let result = match &self.codec {
+            Some(codec) if chunk.id == ChunkId::DTBL => {
+                chunk.decompress_limited(codec.as_ref(), self.limits.max_chunk_size)
+            }
+            _ => {
+                let crc = self.checksum.compute(&chunk.data);
+                if crc == chunk.crc {
+                    Ok(())
+                } else {
+                    Err(BdfError::CrcMismatch {
+                        chunk_index: chunk.index,
+                        expected: chunk.crc,
+                        actual: crc,
+                    })
+                }
+            }
+        };
+
+        result.map_err(|error| match error {
+            BdfError::CrcMismatch {
+                chunk_index,
+                expected,
+                actual,
+            } => Problem::CrcMismatch {
+                chunk_index,
+                name: chunk.id.to_string(),
+                expected,
+                actual,
+            },
+            error => Problem::InvalidChunk {
+                chunk_index: chunk.index,
+                name: chunk.id.to_string(),
+                error,
+            },
+        })
+    }
+
+    /// Marks the data chunk following a bloom filter or statistics as skipped
+    /// if they rule out all targets of the running lookup or don't match the stats filter.
+    /// Damaged filters and statistics never cause a chunk to be skipped.
+    fn filter_chunk(&mut self, chunk: &GenericChunk) {
+        let filters_data = chunk.id == ChunkId::BLOM || chunk.id == ChunkId::STAT;
+        if filters_data && chunk.verify_crc().is_err() {
+            return;
+        }
+        if chunk.id == ChunkId::BLOM {
+            self.skip_next_data |= match (&self.query, BloomFilter::try_from(chunk.clone())) {
+                (Some(query), Ok(filter)) if filter.hash_id == query.hash_id => {
+                    !query.targets.iter().any(|target| filter.contains(target))
+                }
+                _ => false,
+            };
+        } else if chunk.id == ChunkId::STAT {
+            if let Ok(stats) = ChunkStats::try_from(chunk.clone()) {
+                let rejected = self
+                    .stats_filter
+                    .as_ref()
+                    .is_some_and(|filter| !filter.matches(&stats));
+                let ruled_out = self.query.as_ref().is_some_and(|query| {
+                    !query.targets.iter().any(|target| {
+                        stats.may_contain_hashes(query.hash_id, &(&target[..]..=&target[..]))
+                    })
+                });
+                self.skip_next_data |= rejected || ruled_out;
+            }
+        } else if chunk.id == ChunkId::DTBL {
+            if self.skip_next_data {
+                self.skipped_chunks.insert(chunk.index);
+            }
+            self.skip_next_data = false;
+        }
+    }
+
+    /// Moves a seekable reader back to the first chunk after the lookup table
+    /// so that the data can be read again. Other readers are left where they are.
+    fn rewind(&mut self) -> Result<(), BdfError> {
+        let seek = match self.seek {
+            Some(seek) if self.lookup_table.is_some() => seek,
+            _ => return Ok(()),
+        };
+        seek(&mut self.reader, self.data_start)?;
+        self.offset = self.data_start;
+        self.chunk_index = self.data_start_chunk;
+        self.data_chunks_read = 0;
+        self.thread_manager = ThreadManager::new(0, None);
+        self.pending.clear();
+        self.eof = false;
+        self.skip_next_data = false;
+        self.skipped_chunks.clear();
+
+        Ok(())
+    }
+
+    /// Reads the metadata and lookup table if they haven't been read yet
+    fn ensure_start(&mut self) -> Result<&HashLookupTable, BdfError> {
+        match self.lookup_table.take() {
+            Some(lookup_table) => Ok(self.lookup_table.insert(lookup_table)),
+            None => self.read_lookup_table(),
+        }
+    }
+
+    /// Returns the next chunk or `None` if the file ends at a chunk boundary.
+    fn next_chunk_raw(&mut self) -> Result<Option<GenericChunk>, BdfError> {
+        let mut chunk = match self.read_chunk()? {
+            Some(chunk) => chunk,
+            None => return Ok(None),
+        };
+        chunk.index = self.chunk_index;
+        self.chunk_index += 1;
+        self.offset += self.version().chunk_overhead() + chunk.length;
+        self.filter_chunk(&chunk);
+
+        Ok(Some(chunk))
+    }
+
+    /// Returns the format version of the file or version 1 if the header wasn't read yet
+    fn version(&self) -> FormatVersion {
+        self.format_version.unwrap_or_default()
+    }
+
+    /// Reads a chunk at the current position of the inner reader
+    fn read_chunk(&mut self) -> Result<Option<GenericChunk>, BdfError> {
+        let (length, id) = match self.read_chunk_header()? {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        // the data is read without allocating the stored length up front
+        // so that a wrong length in a short file can't cause a huge allocation
+        let mut data = Vec::new();
+        (&mut self.reader).take(length).read_to_end(&mut data)?;
+        if (data.len() as u64) < length {
+            return Err(BdfError::Truncated);
+        }
+        let mut crc_raw = [0u8; 4];
+        self.read_chunk_part(&mut crc_raw)?;
+        let crc = BigEndian::read_u32(&crc_raw);
+
+        Ok(Some(GenericChunk {
+            length,
+            id,
+            data,
+            crc,
+            index: 0,
+            max_entry_size: self.limits.max_entry_size,
+        }))
+    }
+
+    /// Reads the length and type of the chunk at the current position of the inner reader
+    /// or returns `None` if the file ends at a chunk boundary
+    fn read_chunk_header(&mut self) -> Result<Option<(u64, ChunkId)>, BdfError> {
+        let mut length_buf = [0u8; 8];
+        let length_raw = &mut length_buf[..self.version().length_size()];
+        let read = self.read_up_to(length_raw)?;
+        if read == 0 {
+            return Ok(None);
+        } else if read < length_raw.len() {
+            return Err(BdfError::Truncated);
+        }
+        let length = BigEndian::read_uint(length_raw, length_raw.len());
+        if length > self.limits.max_chunk_size {
+            return Err(BdfError::LimitExceeded {
+                what: "chunk size",
+                size: length,
+                limit: self.limits.max_chunk_size,
+            });
+        }
+        let mut id_raw = [0u8; 4];
+        self.read_chunk_part(&mut id_raw)?;
+
+        Ok(Some((length, ChunkId::new(id_raw))))
+    }
+
+    /// Fills the buffer until it is full or the end of the file is reached
+    /// and returns the number of bytes read
+    fn read_up_to(&mut self, buf: &mut [u8]) -> Result<usize, BdfError> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.reader.read(&mut buf[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(BdfError::Io(err)),
+            }
+        }
+
+        Ok(read)
+    }
+
+    /// Reads the exact number of bytes of a chunk that has already been started.
+    /// Reaching the end of the file here means the chunk is truncated.
+    fn read_chunk_part(&mut self, buf: &mut [u8]) -> Result<(), BdfError> {
+        self.reader.read_exact(buf).map_err(|err| {
+            if err.kind() == ErrorKind::UnexpectedEof {
+                BdfError::Truncated
+            } else {
+                BdfError::Io(err)
+            }
+        })
+    }
+}
+
+impl<R: Read + Seek> BDFReader<R> {
+    /// Creates a new BDFReader for a seekable input.
+    /// Hash lookups go back to the first data chunk when they are finished,
+    /// so the file can be searched again or read with [`BDFReader::entries`].
+    pub fn new_seekable(inner: R) -> Self {
+        let mut reader = Self::new(inner);
+        reader.seek = Some(seek_reader::<R>);
+
+        reader
+    }
+
+    /// Returns the index of all data chunks.
+    /// The index is read from the `CIDX` chunk at the end of the file or rebuilt
+    /// by scanning all chunks if the file doesn't contain one.
+    /// The position for sequential reading isn't changed.
+    pub fn read_chunk_index(&mut self) -> Result<&ChunkIndex, BdfError> {
+        let index = match self.index.take() {
+            Some(index) => index,
+            None => {
+                self.ensure_start()?;
+                let position = self.reader.stream_position()?;
+                let result = match self.find_chunk_index() {
+                    Ok(Some(index)) => Ok(index),
+                    Ok(None) => self.scan_chunk_index(),
+                    Err(err) => Err(err),
+                };
+                self.reader.seek(SeekFrom::Start(position))?;
+                result?
+            }
+        };
+
+        Ok(self.index.insert(index))
+    }
+
+    /// Returns the nth data chunk of the file with its data decompressed.
+    /// The position for sequential reading isn't changed.
+    pub fn seek_chunk(&mut self, n: usize) -> Result<GenericChunk, BdfError> {
+        let index_entry = *self
+            .read_chunk_index()?
+            .entries
+            .get(n)
+            .ok_or(BdfError::OutOfRange(n as u64))?;
+        let position = self.reader.stream_position()?;
+        let result = self.read_chunk_at(index_entry.offset);
+        self.reader.seek(SeekFrom::Start(position))?;
+        let mut chunk = result?.ok_or(BdfError::Truncated)?;
+        if chunk.id != ChunkId::DTBL {
+            return Err(BdfError::WrongChunkType {
+                expected: DTBL_CHUNK_NAME.to_string(),
+                found: chunk.id.to_string(),
+            });
+        }
+        chunk.index = n as u64;
+        if let Some(codec) = &self.codec {
+            chunk.decompress_limited(codec.as_ref(), self.limits.max_chunk_size)?;
+        } else {
+            self.check_crc(&chunk)?;
+        }
+
+        Ok(chunk)
+    }
+
+    /// Returns the nth data entry of the file.
+    /// The position for sequential reading isn't changed.
+    pub fn seek_entry(&mut self, n: u64) -> Result<DataEntry, BdfError> {
+        let (chunk_number, entry_number) = self
+            .read_chunk_index()?
+            .locate_entry(n)
+            .ok_or(BdfError::OutOfRange(n))?;
+        let chunk = self.seek_chunk(chunk_number)?;
+        let lookup_table = self.ensure_start()?;
+        let entry = chunk
+            .entry_refs(lookup_table)?
+            .nth(entry_number as usize)
+            .ok_or(BdfError::OutOfRange(n))??;
+
+        Ok(entry.to_data_entry())
+    }
+
+    /// Returns the `SORT` chunk of a file written with [`BDFWriter::set_sort_by`]
+    /// or `None` if the data entries aren't sorted.
+    /// The chunk is searched between the last data chunk and the chunk index.
+    pub fn read_sort_index(&mut self) -> Result<Option<SortIndex>, BdfError> {
+        self.find_trailing_chunk(ChunkId::SORT)?
+            .map(SortIndex::try_from)
+            .transpose()
+    }
+
+    /// Returns the metadata with the real number of entries and chunks.
+    /// For files written without knowing the number of entries up front
+    /// the metadata is read from the trailing `META` chunk.
+    pub fn read_final_metadata(&mut self) -> Result<&MetaChunk, BdfError> {
+        self.ensure_start()?;
+        let counts_known = self.metadata.as_ref().is_some_and(MetaChunk::counts_known);
+        if !counts_known {
+            if let Some(chunk) = self.find_trailing_chunk(ChunkId::META)? {
+                self.metadata = Some(chunk.try_into()?);
+            }
+        }
+
+        self.metadata.as_ref().ok_or(BdfError::Truncated)
+    }
+
+    /// Returns the statistics of the data chunks that have a `stat` chunk
+    /// together with the number of the data chunk. Only the headers of the
+    /// other chunks are read, so no data chunk is read or decompressed.
+    /// The position for sequential reading isn't changed.
+    pub fn read_chunk_stats(&mut self) -> Result<Vec<(u64, ChunkStats)>, BdfError> {
+        self.ensure_start()?;
+        let position = self.reader.stream_position()?;
+        let result = self.scan_chunk_stats();
+        self.reader.seek(SeekFrom::Start(position))?;
+
+        result
+    }
+
+    /// Walks over the chunks after the lookup table and decodes the `stat` chunks
+    fn scan_chunk_stats(&mut self) -> Result<Vec<(u64, ChunkStats)>, BdfError> {
+        let overhead = self.version().chunk_overhead();
+        let mut all_stats = Vec::new();
+        let mut pending = None;
+        let mut chunk_number = 0;
+        let mut offset = self.data_start;
+        self.reader.seek(SeekFrom::Start(offset))?;
+        while let Some((length, id)) = self.read_chunk_header()? {
+            if id == ChunkId::STAT {
+                let chunk = self.read_chunk_at(offset)?.ok_or(BdfError::Truncated)?;
+                self.check_crc(&chunk)?;
+                pending = Some(ChunkStats::try_from(chunk)?);
+            } else if id == ChunkId::DTBL {
+                if let Some(stats) = pending.take() {
+                    all_stats.push((chunk_number, stats));
+                }
+                chunk_number += 1;
+            }
+            offset = chunk_end(offset, length, overhead)?;
+            self.reader.seek(SeekFrom::Start(offset))?;
+        }
+
+        Ok(all_stats)
+    }
+
+    /// Returns the first chunk with the given name between the last data chunk
+    /// and the chunk index or end of the file.
+    /// Without a `CIDX` chunk the chunks are found by their headers,
+    /// so the data chunks are skipped without reading them.
+    fn find_trailing_chunk(&mut self, id: ChunkId) -> Result<Option<GenericChunk>, BdfError> {
+        self.ensure_start()?;
+        let position = self.reader.stream_position()?;
+        let result = self.scan_trailing_chunk(id);
+        self.reader.seek(SeekFrom::Start(position))?;
+
+        result
+    }
+
+    /// Walks over the chunk headers from the end of the last indexed data chunk
+    /// or the start of the data and reads the first chunk with the given name
+    fn scan_trailing_chunk(&mut self, id: ChunkId) -> Result<Option<GenericChunk>, BdfError> {
+        if self.index.is_none() {
+            self.index = self.find_chunk_index()?;
+        }
+        let overhead = self.version().chunk_overhead();
+        let mut offset = match self.index.as_ref().and_then(|index| index.entries.last()) {
+            Some(last) => chunk_end(last.offset, last.length, overhead)?,
+            None => self.data_start,
+        };
+        self.reader.seek(SeekFrom::Start(offset))?;
+        while let Some((length, chunk_id)) = self.read_chunk_header()? {
+            if chunk_id == id {
+                return self.read_chunk_at(offset);
+            }
+            if chunk_id == ChunkId::CIDX {
+                break;
+            }
+            offset = chunk_end(offset, length, overhead)?;
+            self.reader.seek(SeekFrom::Start(offset))?;
+        }
+
+        Ok(None)
+    }
+
+    /// Searches the entries of a file sorted by the hash function `hash_name`
+    /// for the given hash value. Only the data chunks whose range of
+    /// values contains the hash are read and decompressed.
+    pub fn find_by_hash_sorted(
+        &mut self,
+        hash_name: &str,
+        hash: &[u8],
+    ) -> Result<Vec<HashMatch>, BdfError> {
+        let lookup_table = self.ensure_start()?.clone();
+        let hash_id = *lookup_table
+            .get_entry(hash_name)
+            .ok_or_else(|| BdfError::UnknownHashName(hash_name.to_string()))?
+            .0;
+        let sort_index = match self.read_sort_index()? {
+            Some(sort_index) if sort_index.hash_id == hash_id => sort_index,
+            _ => return Err(BdfError::NotSortedBy(hash_name.to_string())),
+        };
+        let mut targets = HashSet::new();
+        targets.insert(hash.to_vec());
+        let mut matches = Vec::new();
+
+        for chunk_number in sort_index.candidate_chunks(hash) {
+            let chunk = self.seek_chunk(chunk_number as usize)?;
+            for (entry_number, entry) in chunk.find_entries(&lookup_table, hash_id, &targets)? {
+                matches.push(HashMatch {
+                    chunk_number,
+                    entry_number,
+                    entry,
+                });
+            }
+        }
+
+        Ok(matches)
+    }
+
+    /// Reads the chunk index using the footer at the end of the file
+    fn find_chunk_index(&mut self) -> Result<Option<ChunkIndex>, BdfError> {
+        let version = self.version();
+        let footer_size = version.footer_size();
+        let length = self.reader.seek(SeekFrom::End(0))?;
+        if length < self.data_start + footer_size {
+            return Ok(None);
+        }
+        self.reader.seek(SeekFrom::Start(length - footer_size))?;
+        let mut footer_raw = vec![0u8; footer_size as usize];
+        self.reader.read_exact(&mut footer_raw)?;
+        let name_start = version.length_size();
+        if &footer_raw[name_start..name_start + 4] != CFTR_CHUNK_NAME.as_bytes() {
+            return Ok(None);
+        }
+        let footer = self
+            .read_chunk_at(length - footer_size)?
+            .ok_or(BdfError::Truncated)?;
+        if self.checksum.compute(&footer.data) != footer.crc {
+            return Ok(None);
+        }
+        let footer: ChunkIndexFooter = footer.try_into()?;
+        let index = self
+            .read_chunk_at(footer.index_offset)?
+            .ok_or(BdfError::Truncated)?;
+
+        Ok(Some(ChunkIndex::parse(index, version)?))
+    }
+
+    /// Builds the chunk index by reading every chunk after the lookup table
+    fn scan_chunk_index(&mut self) -> Result<ChunkIndex, BdfError> {
+        let mut index = ChunkIndex::default();
+        let mut offset = self.data_start;
+        while let Some(mut chunk) = self.read_chunk_at(offset)? {
+            let length = chunk.length;
+            if chunk.id == ChunkId::DTBL {
+                if let Some(codec) = &self.codec {
+                    chunk.decompress_limited(codec.as_ref(), self.limits.max_chunk_size)?;
+                }
+                index.entries.push(ChunkIndexEntry {
+                    offset,
+                    length,
+                    entry_count: chunk.entry_count()?,
+                });
+            }
+            offset += self.version().chunk_overhead() + length;
+        }
+
+        Ok(index)
+    }
+
+    /// Returns an iterator over the data entries that can be recovered from a
+    /// damaged or truncated file. Damaged chunks are skipped by searching for the next
+    /// chunk with a plausible name and a matching crc sum, and the complete rows of a
+    /// truncated data chunk at the end of the file are recovered.
+    /// What was lost is recorded in [`Salvage::report`].
+    /// The metadata and lookup table have to be intact.
+    pub fn salvage(&mut self) -> Salvage<'_, R> {
+        Salvage {
+            reader: self,
+            lookup_table: None,
+            offset: 0,
+            end: 0,
+            current: Vec::new().into_iter(),
+            finished: false,
+            report: SalvageReport::default(),
+        }
+    }
+
+    /// Reads the chunk at the offset and checks whether it is intact.
+    /// The data of a chunk running past the end isn't read.
+    fn salvage_chunk_at(&mut self, offset: u64, end: u64) -> Result<SalvagedChunk, BdfError> {
+        let version = self.version();
+        let length_size = version.length_size();
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let mut header_buf = [0u8; 12];
+        let header = &mut header_buf[..length_size + 4];
+        if self.read_up_to(header)? < header.len() || !plausible_name(&header[length_size..]) {
+            return Ok(SalvagedChunk::Invalid);
+        }
+        let length = BigEndian::read_uint(&header[..length_size], length_size);
+        let name = String::from_utf8_lossy(&header[length_size..]).into_owned();
+        if offset.saturating_add(version.chunk_overhead().saturating_add(length)) > end {
+            if length > self.limits.max_chunk_size {
+                return Ok(SalvagedChunk::Damaged(name, BdfError::Truncated));
+            }
+            return Ok(SalvagedChunk::Truncated(name, length));
+        }
+        let mut chunk = match self.read_chunk_at(offset) {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => return Ok(SalvagedChunk::Invalid),
+            Err(BdfError::Io(err)) => return Err(BdfError::Io(err)),
+            Err(err) => return Ok(SalvagedChunk::Damaged(name, err)),
+        };
+        let result = match &self.codec {
+            Some(codec) if chunk.id == ChunkId::DTBL => {
+                chunk.decompress_limited(codec.as_ref(), self.limits.max_chunk_size)
+            }
+            _ if self.checksum.compute(&chunk.data) == chunk.crc => Ok(()),
+            _ => Err(BdfError::CrcMismatch {
+                chunk_index: chunk.index,
+                expected: chunk.crc,
+                actual: self.checksum.compute(&chunk.data),
+            }),
+        };
+
+        Ok(match result {
+            Ok(()) => SalvagedChunk::Intact(chunk, length),
+            Err(err) => SalvagedChunk::Damaged(name, err),
+        })
+    }
+
+    /// Reads the available data of the chunk at the offset that is cut off at the end
+    fn read_truncated_data(
+        &mut self,
+        offset: u64,
+        length: u64,
+        end: u64,
+    ) -> Result<Vec<u8>, BdfError> {
+        let header_size = self.version().length_size() as u64 + 4;
+        self.reader.seek(SeekFrom::Start(offset + header_size))?;
+        let available = end.saturating_sub(offset + header_size);
+        let mut data = Vec::new();
+        (&mut self.reader)
+            .take(available.min(length))
+            .read_to_end(&mut data)?;
+
+        Ok(data)
+    }
+
+    /// Returns the offset of the next intact chunk after the given offset
+    fn find_intact_chunk(&mut self, from: u64, end: u64) -> Result<Option<u64>, BdfError> {
+        let version = self.version();
+        let name_start = version.length_size();
+        let mut position = from;
+        while position + version.chunk_overhead() <= end {
+            self.reader.seek(SeekFrom::Start(position))?;
+            let mut window = vec![0u8; SALVAGE_WINDOW.min((end - position) as usize)];
+            self.reader.read_exact(&mut window)?;
+            for i in 0..=(window.len() - name_start - 4) {
+                if !plausible_name(&window[i + name_start..i + name_start + 4]) {
+                    continue;
+                }
+                let offset = position + i as u64;
+                // chunks running past the end can't be intact, so they aren't read
+                let length = BigEndian::read_uint(&window[i..i + name_start], name_start);
+                if offset.saturating_add(version.chunk_overhead().saturating_add(length)) > end {
+                    continue;
+                }
+                if let SalvagedChunk::Intact(..) = self.salvage_chunk_at(offset, end)? {
+                    return Ok(Some(offset));
+                }
+            }
+            position += (window.len() - name_start - 3) as u64;
+        }
+
+        Ok(None)
+    }
+
+    /// Reads the chunk at the given offset
+    fn read_chunk_at(&mut self, offset: u64) -> Result<Option<GenericChunk>, BdfError> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.read_chunk()
+    }
+}
+
+impl<'a, R: Read + Seek> Salvage<'a, R> {
+    /// Returns what was recovered and lost so far
+    pub fn report(&self) -> &SalvageReport {
+        &self.report
+    }
+
+    /// Returns the report after the iterator was consumed
+    pub fn into_report(self) -> SalvageReport {
+        self.report
+    }
+
+    /// Returns the entries of the next data chunk that could be recovered
+    fn next_entries(&mut self) -> Result<Option<Vec<DataEntry>>, BdfError> {
+        let lookup_table = match self.lookup_table.take() {
+            Some(lookup_table) => lookup_table,
+            None => {
+                let lookup_table = self.reader.ensure_start()?.clone();
+                self.offset = self.reader.data_start;
+                self.end = self.reader.reader.seek(SeekFrom::End(0))?;
+                lookup_table
+            }
+        };
+        let lookup_table = self.lookup_table.insert(lookup_table);
+
+        while self.offset < self.end {
+            let offset = self.offset;
+            let damage = match self.reader.salvage_chunk_at(offset, self.end)? {
+                SalvagedChunk::Intact(mut chunk, length) => {
+                    self.offset += self.reader.version().chunk_overhead() + length;
+                    if chunk.id != ChunkId::DTBL {
+                        continue;
+                    }
+                    match chunk.data_entries(lookup_table) {
+                        Ok(entries) => {
+                            self.report.chunks_recovered += 1;
+                            self.report.entries_recovered += entries.len() as u64;
+                            return Ok(Some(entries));
+                        }
+                        Err(err) => {
+                            self.report.losses.push(Loss::DamagedChunk {
+                                offset,
+                                length: self.offset - offset,
+                                name: chunk.id.to_string(),
+                                error: err,
+                            });
+                            continue;
+                        }
+                    }
+                }
+                SalvagedChunk::Damaged(name, error) => Some((name, error)),
+                SalvagedChunk::Invalid => None,
+                SalvagedChunk::Truncated(name, length) => {
+                    match self.reader.find_intact_chunk(offset + 1, self.end)? {
+                        Some(next) => {
+                            self.report.losses.push(Loss::DamagedChunk {
+                                offset,
+                                length: next - offset,
+                                name,
+                                error: BdfError::Truncated,
+                            });
+                            self.offset = next;
+                            continue;
+                        }
+                        None => {
+                            let data = self.reader.read_truncated_data(offset, length, self.end)?;
+                            let entries = self.reader.salvage_truncated(&name, data, lookup_table);
+                            self.report.losses.push(Loss::TruncatedChunk {
+                                offset,
+                                missing: offset
+                                    .saturating_add(
+                                        self.reader
+                                            .version()
+                                            .chunk_overhead()
+                                            .saturating_add(length),
+                                    )
+                                    .saturating_sub(self.end),
+                                name,
+                                entries_recovered: entries.len() as u32,
+                            });
+                            self.report.entries_recovered += entries.len() as u64;
+                            self.offset = self.end;
+                            return Ok(Some(entries));
+                        }
+                    }
+                }
+            };
+            let next = self.reader.find_intact_chunk(offset + 1, self.end)?;
+            self.offset = next.unwrap_or(self.end);
+            let length = self.offset - offset;
+            self.report.losses.push(match damage {
+                Some((name, error)) => Loss::DamagedChunk {
+                    offset,
+                    length,
+                    name,
+                    error,
+                },
+                None => Loss::Unreadable { offset, length },
+            });
+        }
+
+        Ok(None)
+    }
+}
+
+impl<R: Read> BDFReader<R> {
+    /// Returns the complete rows of a data chunk that was cut off at the end of the file
+    fn salvage_truncated(
+        &self,
+        name: &str,
+        data: Vec<u8>,
+        lookup_table: &HashLookupTable,
+    ) -> Vec<DataEntry> {
+        if name != DTBL_CHUNK_NAME {
+            return Vec::new();
+        }
+        let data = match &self.codec {
+            Some(codec) => codec.decompress_partial(&data),
+            None => data,
+        };
+
+        GenericChunk::new(ChunkId::DTBL, data).complete_entries(lookup_table)
+    }
+}
+
+/// Returns the offset after the chunk at the given offset.
+/// Returns an error if an offset or length read from the file points past any possible end.
+fn chunk_end(offset: u64, length: u64, overhead: u64) -> Result<u64, BdfError> {
+    offset
+        .checked_add(length)
+        .and_then(|end| end.checked_add(overhead))
+        .ok_or(BdfError::Truncated)
+}
+
+/// Returns true if the bytes can be the name of a chunk
+fn plausible_name(name: &[u8]) -> bool {
+    name.iter().all(u8::is_ascii_alphabetic)
+}
+
+impl<'a, R: Read + Seek> Iterator for Salvage<'a, R> {
+    type Item = Result<DataEntry, BdfError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.current.next() {
+                return Some(Ok(entry));
+            }
+            if self.finished {
+                return None;
+            }
+            match self.next_entries() {
+                Ok(Some(entries)) => self.current = entries.into_iter(),
+                Ok(None) => self.finished = true,
+                Err(err) => {
+                    self.finished = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+impl<'a, R: Read> Iterator for Chunks<'a, R> {
+    type Item = Result<GenericChunk, BdfError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let result = match self.reader.ensure_start() {
+            Ok(_) => self.reader.next_chunk(),
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(Some(chunk)) => Some(Ok(chunk)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(err) => {
+                self.finished = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl<'a, R: Read> Iterator for Entries<'a, R> {
+    type Item = Result<DataEntry, BdfError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.current.next() {
+                return Some(Ok(entry));
+            }
+            let mut chunk = match self.chunks.next()? {
+                Ok(chunk) => chunk,
+                Err(err) => return Some(Err(err)),
+            };
+            if chunk.id != ChunkId::DTBL {
+                continue;
+            }
+            if self.lookup_table.is_none() {
+                self.lookup_table = self.chunks.reader.lookup_table.clone();
+            }
+            let lookup_table = self.lookup_table.as_ref()?;
+            match chunk.data_entries(lookup_table) {
+                Ok(entries) => self.current = entries.into_iter(),
+                Err(err) => {
+                    self.chunks.finished = true;
+                    return Some(Err(err));
+                }
+            }
+        }
     }
 }
@@ -2,129 +2,1636 @@
 mod tests {
 
     use super::io::BDFWriter;
-    use crate::chunks::{DataEntry, HashEntry};
-    use crate::io::BDFReader;
-    use std::fs::{remove_file, File};
-    use std::io::Error;
+    use crate::builder::{BDFReaderBuilder, BDFWriterBuilder};
+    use crate::chunks::{
+        BloomFilter, Checksum, Chunk, ChunkId, ChunkIndex, ChunkIndexEntry, DataEntry,
+        DataEntryRef, FormatVersion, GenericChunk, HashEntry, HashLookupTable, InfoChunk,
+        InfoValue, BDF_HDR, HEADER_SIZE, META_CHUNK_NAME,
+    };
+    use crate::codec::{Codec, CodecRegistry};
+    use crate::error::BdfError;
+    use crate::executor::{Executor, Job};
+    use crate::io::{BDFReader, ReadLimits, StatsFilter, Validation};
+    use crate::registry::ChunkDecoder;
+    use crate::salvage::Loss;
+    use crate::verify::Problem;
+    use std::any::Any;
+    use std::cell::Cell;
+    use std::collections::{HashMap, HashSet};
+    use std::convert::TryFrom;
+    use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+    use std::rc::Rc;
+    use std::sync::Arc;
 
     const FOO: &str = "foo";
     const BAR: &str = "bar";
 
+    /// A codec that inverts every byte
+    #[derive(Debug)]
+    struct InvertCodec;
+
+    /// A reader that counts the bytes read from it
+    #[derive(Debug)]
+    struct CountingReader {
+        inner: Cursor<Vec<u8>>,
+        bytes_read: Rc<Cell<u64>>,
+    }
+
+    impl Read for CountingReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let read = self.inner.read(buf)?;
+            self.bytes_read.set(self.bytes_read.get() + read as u64);
+            Ok(read)
+        }
+    }
+
+    impl Seek for CountingReader {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    impl Codec for InvertCodec {
+        fn name(&self) -> &str {
+            "invt"
+        }
+
+        fn compress(&self, data: &[u8], _level: u32) -> Result<Vec<u8>, BdfError> {
+            Ok(data.iter().map(|byte| !byte).collect())
+        }
+
+        fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, BdfError> {
+            Ok(data.iter().map(|byte| !byte).collect())
+        }
+    }
+
+    /// A codec that fails to compress and panics while decompressing
+    #[derive(Debug)]
+    struct BrokenCodec(&'static str);
+
+    impl Codec for BrokenCodec {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn compress(&self, _data: &[u8], _level: u32) -> Result<Vec<u8>, BdfError> {
+            Err(BdfError::UnsupportedCompression(self.0.to_string()))
+        }
+
+        fn decompress(&self, _data: &[u8]) -> Result<Vec<u8>, BdfError> {
+            panic!("broken codec")
+        }
+    }
+
+    /// An executor that runs every job on the calling thread
+    #[derive(Debug)]
+    struct InlineExecutor;
+
+    impl Executor for InlineExecutor {
+        fn execute(&self, job: Job) {
+            job()
+        }
+    }
+
+    /// A custom chunk storing a note
+    #[derive(Debug, PartialEq)]
+    struct NoteChunk(ChunkId, Vec<u8>);
+
+    impl Chunk for NoteChunk {
+        fn id(&self) -> ChunkId {
+            self.0
+        }
+
+        fn serialize_data(&self) -> Vec<u8> {
+            self.1.clone()
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    /// Decodes [`NoteChunk`]s of one type
+    #[derive(Debug)]
+    struct NoteDecoder(ChunkId);
+
+    impl ChunkDecoder for NoteDecoder {
+        fn id(&self) -> ChunkId {
+            self.0
+        }
+
+        fn decode(&self, data: &[u8]) -> Result<Box<dyn Chunk>, BdfError> {
+            Ok(Box::new(NoteChunk(self.0, data.to_vec())))
+        }
+    }
+
     #[test]
-    fn it_writes_uncompressed() -> Result<(), Error> {
-        let mut writer = new_writer("tmp1.bdf", 2, false)?;
+    fn it_writes_uncompressed() -> Result<(), BdfError> {
+        let mut writer = BDFWriter::new(Vec::new(), 2, false);
         writer.set_entries_per_chunk(24)?;
 
         writer.add_lookup_entry(HashEntry::new(BAR.to_string(), 5))?;
         writer.add_lookup_entry(HashEntry::new(FOO.to_string(), 4))?;
 
         let mut entry_1 = DataEntry::new("lol".to_string());
-        entry_1.add_hash_value(FOO.to_string(), vec![0, 1, 0, 2]);
-        entry_1.add_hash_value(BAR.to_string(), vec![0, 2, 3, 4, 5]);
+        entry_1.add_hash_value(writer.lookup_table(), FOO, &[0, 1, 0, 2])?;
+        entry_1.add_hash_value(writer.lookup_table(), BAR, &[0, 2, 3, 4, 5])?;
         writer.add_data_entry(entry_1)?;
 
         let mut entry_2 = DataEntry::new("lel".to_string());
-        entry_2.add_hash_value(BAR.to_string(), vec![0, 3, 2, 1, 5]);
-        entry_2.add_hash_value(FOO.to_string(), vec![4, 5, 2, 3]);
+        entry_2.add_hash_value(writer.lookup_table(), BAR, &[0, 3, 2, 1, 5])?;
+        entry_2.add_hash_value(writer.lookup_table(), FOO, &[4, 5, 2, 3])?;
         writer.add_data_entry(entry_2)?;
 
         writer.finish()?;
-        remove_file("tmp1.bdf")?;
+        assert!(!writer.into_inner()?.is_empty());
 
         Ok(())
     }
 
     #[test]
-    fn it_writes_compressed() -> Result<(), Error> {
-        let mut writer = new_writer("tmp2.bdf", 4, true)?;
+    fn it_writes_compressed() -> Result<(), BdfError> {
+        let mut writer = BDFWriter::new(Vec::new(), 4, true);
         writer.set_compression_level(3);
         writer.set_entries_per_chunk(3)?;
 
-        writer.add_lookup_entry(HashEntry::new(FOO.to_string(), 4))?;
-        writer.add_lookup_entry(HashEntry::new(BAR.to_string(), 5))?;
+        let foo = writer.add_lookup_entry(HashEntry::new(FOO.to_string(), 4))?;
+        let bar = writer.add_lookup_entry(HashEntry::new(BAR.to_string(), 5))?;
 
         let mut entry_1 = DataEntry::new("lol".to_string());
-        entry_1.add_hash_value(FOO.to_string(), vec![2, 4, 0, 2]);
-        entry_1.add_hash_value(BAR.to_string(), vec![5, 2, 1, 4, 5]);
+        entry_1.add_hash(foo, &[2, 4, 0, 2]);
+        entry_1.add_hash(bar, &[5, 2, 1, 4, 5]);
         writer.add_data_entry(entry_1)?;
 
         let mut entry_2 = DataEntry::new("lel".to_string());
-        entry_2.add_hash_value(BAR.to_string(), vec![0, 3, 2, 1, 5]);
-        entry_2.add_hash_value(FOO.to_string(), vec![4, 5, 2, 3]);
+        entry_2.add_hash(bar, &[0, 3, 2, 1, 5]);
+        entry_2.add_hash(foo, &[4, 5, 2, 3]);
         writer.add_data_entry(entry_2)?;
 
         let mut entry_3 = DataEntry::new("lool".to_string());
-        entry_3.add_hash_value(BAR.to_string(), vec![1, 3, 2, 1, 5]);
-        entry_3.add_hash_value(FOO.to_string(), vec![5, 5, 2, 3]);
+        entry_3.add_hash(bar, &[1, 3, 2, 1, 5]);
+        entry_3.add_hash(foo, &[5, 5, 2, 3]);
         writer.add_data_entry(entry_3)?;
 
         let mut entry_4 = DataEntry::new("lool".to_string());
-        entry_4.add_hash_value(BAR.to_string(), vec![1, 3, 2, 1, 5]);
-        entry_4.add_hash_value(FOO.to_string(), vec![5, 5, 2, 3]);
+        entry_4.add_hash(bar, &[1, 3, 2, 1, 5]);
+        entry_4.add_hash(foo, &[5, 5, 2, 3]);
         writer.add_data_entry(entry_4)?;
 
         writer.finish()?;
+        assert!(!writer.into_inner()?.is_empty());
 
-        remove_file("tmp2.bdf")?;
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_more_chunks_than_workers() -> Result<(), BdfError> {
+        let mut writer = BDFWriter::new(Vec::new(), 200, true);
+        writer.set_entries_per_chunk(1)?;
+        let foo = writer.add_lookup_entry(HashEntry::new(FOO.to_string(), 4))?;
+        for i in 0..200u32 {
+            let mut entry = DataEntry::new(i.to_string());
+            entry.add_hash(foo, &i.to_be_bytes());
+            writer.add_data_entry(entry)?;
+        }
+        writer.finish()?;
+
+        let mut reader = new_reader(writer.into_inner()?);
+        assert_eq!(reader.entries().count(), 200);
 
         Ok(())
     }
 
     #[test]
-    fn it_reads() -> Result<(), Error> {
-        create_simple_file("tmp3.bdf", false)?;
-        let mut reader = new_reader("tmp3.bdf")?;
+    fn it_reads() -> Result<(), BdfError> {
+        let mut reader = new_reader(create_simple_file(false)?);
         reader.read_start()?;
         let lookup_table = &reader.lookup_table.clone().unwrap();
-        let mut next_chunk = reader.next_chunk()?;
+        let mut next_chunk = reader.next_chunk()?.expect("missing data chunk");
         let data_entries = next_chunk.data_entries(lookup_table)?;
-        assert_eq!(data_entries[0].plain, "lol".to_string());
-
-        remove_file("tmp3.bdf")?;
+        assert_eq!(data_entries[0].plain, b"lol");
 
         Ok(())
     }
 
     #[test]
-    fn it_reads_compressed() -> Result<(), Error> {
-        create_simple_file("tmp4.bdf", true)?;
-        let mut reader = new_reader("tmp4.bdf")?;
+    fn it_reads_compressed() -> Result<(), BdfError> {
+        let mut reader = new_reader(create_simple_file(true)?);
         reader.read_metadata()?;
         let lookup_table = &reader.read_lookup_table()?.clone();
-        let mut next_chunk = reader.next_chunk()?;
+        let mut next_chunk = reader.next_chunk()?.expect("missing data chunk");
         let data_entries = next_chunk.data_entries(lookup_table)?;
-        assert_eq!(data_entries[0].plain, "lol".to_string());
+        assert_eq!(data_entries[0].plain, b"lol");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_compresses_with_every_codec() -> Result<(), BdfError> {
+        for method in CodecRegistry::default().names() {
+            let mut writer = BDFWriter::new(Vec::new(), 10, false);
+            writer.set_compression_method(Some(method))?;
+            let data = write_numbered(writer, 10, 3)?;
+
+            let mut reader = new_reader(data);
+            assert_eq!(
+                reader.read_metadata()?.compression_method.as_deref(),
+                Some(method)
+            );
+            let plains = reader
+                .entries()
+                .map(|entry| entry.and_then(plain_string))
+                .collect::<Result<Vec<String>, BdfError>>()?;
+            assert_eq!(plains.len(), 10);
+            assert_eq!(plains[9], "9");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_registered_codecs() -> Result<(), BdfError> {
+        let mut writer = BDFWriter::new(Vec::new(), 10, false);
+        writer.set_codec(Some(Arc::new(InvertCodec)))?;
+        let data = write_numbered(writer, 10, 3)?;
+
+        assert!(matches!(
+            new_reader(data.clone()).read_metadata(),
+            Err(BdfError::UnsupportedCompression(_))
+        ));
+        let mut reader = new_reader(data);
+        reader.register_codec(Arc::new(InvertCodec))?;
+        assert_eq!(reader.entries().count(), 10);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_patches_counts_of_seekable_outputs() -> Result<(), BdfError> {
+        for count in [9u64, 10] {
+            let writer = BDFWriter::new_seekable(Cursor::new(Vec::new()), true);
+            let data = write_numbered(writer, count, 3)?.into_inner();
+
+            let mut reader = new_reader(data);
+            let metadata = reader.read_metadata()?.clone();
+            assert_eq!(metadata.entry_count, count);
+            assert_eq!(metadata.chunk_count, (count as u32).div_ceil(3));
+            let chunk_count = reader.read_chunk_index()?.entries.len();
+            assert_eq!(chunk_count, metadata.chunk_count as usize);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_trailing_metadata() -> Result<(), BdfError> {
+        let writer = BDFWriter::new_streaming(Vec::new(), true);
+        let mut data = write_numbered(writer, 10, 3)?;
+
+        let mut reader = new_reader(data.clone());
+        assert!(!reader.read_metadata()?.counts_known());
+        assert_eq!(reader.entries().count(), 10);
+        assert_eq!(
+            reader.metadata.as_ref().map(|meta| meta.entry_count),
+            Some(10)
+        );
+
+        // the data chunks are skipped, so a damaged one doesn't matter
+        let id = data
+            .windows(4)
+            .position(|window| window == b"DTBL")
+            .unwrap();
+        data[id + 8] ^= 0xFF;
+        let metadata = new_reader(data).read_final_metadata()?.clone();
+        assert_eq!(metadata.entry_count, 10);
+        assert_eq!(metadata.chunk_count, 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_chunks_in_order() -> Result<(), BdfError> {
+        let mut reader = new_reader(create_numbered_file(500, 1, true)?);
+        let plains = reader
+            .entries()
+            .map(|entry| entry.and_then(plain_string))
+            .collect::<Result<Vec<String>, BdfError>>()?;
+        let expected: Vec<String> = (0..500).map(|i| i.to_string()).collect();
+        assert_eq!(plains, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_reproducible_files() -> Result<(), BdfError> {
+        let write = || -> Result<Vec<u8>, BdfError> {
+            let mut writer = BDFWriter::new(Vec::new(), 50, true);
+            writer.set_entries_per_chunk(4)?;
+            writer.set_write_index(true)?;
+            for name in ["md5", "sha1", "sha256", "ntlm"] {
+                writer.add_lookup_entry(HashEntry::new(name.to_string(), 4))?;
+            }
+            for i in 0..50u32 {
+                let mut entry = DataEntry::new(i.to_string());
+                for (offset, name) in ["md5", "sha1", "sha256", "ntlm"].iter().enumerate() {
+                    let value = i.wrapping_mul(offset as u32 + 7);
+                    entry.add_hash_value(writer.lookup_table(), name, &value.to_be_bytes())?;
+                }
+                writer.add_data_entry(entry)?;
+            }
+            writer.finish()?;
+
+            writer.into_inner()
+        };
+
+        assert_eq!(write()?, write()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_propagates_worker_errors() -> Result<(), BdfError> {
+        let mut writer = BDFWriter::new(Vec::new(), 10, false);
+        writer.set_codec(Some(Arc::new(BrokenCodec("brkn"))))?;
+        let result = add_numbered_entries(&mut writer, 10, 3).and_then(|_| writer.finish());
+        assert!(matches!(result, Err(BdfError::UnsupportedCompression(_))));
+
+        let mut writer = BDFWriter::new(Vec::new(), 10, false);
+        writer.set_codec(Some(Arc::new(InvertCodec)))?;
+        let data = write_numbered(writer, 10, 3)?;
+        let mut reader = new_reader(data);
+        reader.register_codec(Arc::new(BrokenCodec("invt")))?;
+        let results: Vec<Result<DataEntry, BdfError>> = reader.entries().collect();
+        assert!(matches!(results[..], [Err(BdfError::WorkerFailed)]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_stops_workers_of_dropped_readers() -> Result<(), BdfError> {
+        let data = create_numbered_file(500, 1, true)?;
+        for _ in 0..10 {
+            let mut reader = new_reader(data.clone());
+            assert_eq!(reader.entries().take(3).count(), 3);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_runs_workers_on_executors() -> Result<(), BdfError> {
+        let executors: Vec<Option<Arc<dyn Executor>>> = vec![
+            None,
+            Some(Arc::new(InlineExecutor)),
+            #[cfg(feature = "rayon")]
+            Some(Arc::new(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(2)
+                    .build()
+                    .unwrap(),
+            )),
+        ];
+
+        for executor in executors {
+            let mut writer = BDFWriter::new(Vec::new(), 50, true);
+            writer.set_threads(1)?;
+            if let Some(executor) = &executor {
+                writer.set_executor(Arc::clone(executor))?;
+            }
+            let data = write_numbered(writer, 50, 2)?;
+
+            let mut reader = new_reader(data);
+            reader.set_threads(1)?;
+            reader.set_prefetch(3)?;
+            if let Some(executor) = executor {
+                reader.set_executor(executor)?;
+            }
+            let plains = reader
+                .entries()
+                .map(|entry| entry.and_then(plain_string))
+                .collect::<Result<Vec<String>, BdfError>>()?;
+            let expected: Vec<String> = (0..50).map(|i| i.to_string()).collect();
+            assert_eq!(plains, expected);
+            assert!(matches!(
+                reader.set_threads(2),
+                Err(BdfError::WorkersStarted)
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_builds_configured_writers_and_readers() -> Result<(), BdfError> {
+        let mut writer = BDFWriterBuilder::new()
+            .compression_method("lzma")
+            .compression_level(3)
+            .bytes_per_chunk(64)
+            .threads(2)
+            .write_index(true)
+            .checksum(Checksum::Crc32)
+            .build_seekable(Cursor::new(Vec::new()))?;
+        add_numbered_entries(&mut writer, 50, 20)?;
+        writer.finish()?;
+        let data = writer.into_inner()?.into_inner();
+
+        let report = new_reader(data.clone()).verify()?;
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert!(report.data_chunks > 50 / 20 + 1);
+
+        let mut reader = BDFReaderBuilder::new()
+            .threads(1)
+            .prefetch(2)
+            .validation(Validation::Strict)
+            .checksum(Checksum::Crc32)
+            .build(Cursor::new(data))?;
+        let plains = reader
+            .entries()
+            .map(|entry| entry.and_then(plain_string))
+            .collect::<Result<Vec<String>, BdfError>>()?;
+        let expected: Vec<String> = (0..50).map(|i| i.to_string()).collect();
+        assert_eq!(plains, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_invalid_configurations() -> Result<(), BdfError> {
+        let invalid_writers = [
+            BDFWriterBuilder::new().entries_per_chunk(0),
+            BDFWriterBuilder::new().threads(0),
+            BDFWriterBuilder::new().compression_level(5),
+            BDFWriterBuilder::new().entry_count(10).bytes_per_chunk(64),
+            BDFWriterBuilder::new().bytes_per_chunk(0),
+            BDFWriterBuilder::new().bytes_per_chunk(u32::MAX as u64 + 1),
+        ];
+        for builder in invalid_writers {
+            let mut output = Vec::new();
+            assert!(matches!(
+                builder.build(&mut output),
+                Err(BdfError::InvalidConfig(_))
+            ));
+            assert!(output.is_empty());
+        }
+        assert!(matches!(
+            BDFWriterBuilder::new()
+                .compression_method("nope")
+                .build(Vec::new()),
+            Err(BdfError::UnsupportedCompression(_))
+        ));
+
+        let path = std::env::temp_dir().join(format!("bdf-invalid-{}.bdf", std::process::id()));
+        std::fs::write(&path, b"precious")?;
+        let bytes_per_chunk = BDFWriterBuilder::new().bytes_per_chunk(0).create(&path);
+        let codec = BDFWriterBuilder::new()
+            .codec(Arc::new(BrokenCodec("toolong")))
+            .create(&path);
+        let contents = std::fs::read(&path)?;
+        std::fs::remove_file(&path)?;
+        assert!(matches!(bytes_per_chunk, Err(BdfError::InvalidConfig(_))));
+        assert!(matches!(codec, Err(BdfError::UnsupportedCompression(_))));
+        assert_eq!(contents, b"precious");
+
+        for builder in [
+            BDFReaderBuilder::new().threads(0),
+            BDFReaderBuilder::new().prefetch(0),
+        ] {
+            assert!(matches!(
+                builder.build(Cursor::new(Vec::new())),
+                Err(BdfError::InvalidConfig(_))
+            ));
+        }
+
+        let mut data = create_simple_file(false)?;
+        let crc_position = data.len() - 1;
+        data[crc_position] ^= 0xff;
+        let mut lenient = new_reader(data.clone());
+        lenient.read_start()?;
+        assert!(lenient.next_chunk()?.is_some());
+        let mut strict = BDFReaderBuilder::new()
+            .validation(Validation::Strict)
+            .build(Cursor::new(data))?;
+        strict.read_start()?;
+        assert!(matches!(
+            strict.next_chunk(),
+            Err(BdfError::CrcMismatch { chunk_index: 2, .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_stores_binary_plaintexts() -> Result<(), BdfError> {
+        let binary = vec![0xff, 0xfe, 0x00, b'a'];
+        for compressed in [false, true] {
+            let mut writer = BDFWriter::new(Vec::new(), 2, compressed);
+            let foo = writer.add_lookup_entry(HashEntry::new(FOO.to_string(), 4))?;
+            for plain in [binary.clone(), b"lol".to_vec()] {
+                let mut entry = DataEntry::new(plain);
+                entry.add_hash(foo, &[1, 2, 3, 4]);
+                writer.add_data_entry(entry)?;
+            }
+            writer.finish()?;
+
+            let mut reader = new_reader(writer.into_inner()?);
+            assert!(!reader.read_metadata()?.utf8);
+            let entries = reader
+                .entries()
+                .collect::<Result<Vec<DataEntry>, BdfError>>()?;
+            assert_eq!(entries[0].plain, binary);
+            assert!(entries[0].plain_str().is_err());
+            assert_eq!(entries[1].plain_str(), Ok("lol"));
+        }
+
+        let mut writer = BDFWriterBuilder::new()
+            .entry_count(1)
+            .utf8(true)
+            .build(Vec::new())?;
+        let foo = writer.add_lookup_entry(HashEntry::new(FOO.to_string(), 4))?;
+        let mut entry = DataEntry::new(binary.clone());
+        entry.add_hash(foo, &[1, 2, 3, 4]);
+        match writer.add_data_entry(entry) {
+            Err(BdfError::InvalidUtf8(err)) => assert_eq!(err.into_bytes(), binary),
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+        let mut entry = DataEntry::new("lol");
+        entry.add_hash(foo, &[1, 2, 3, 4]);
+        writer.add_data_entry(entry)?;
+        writer.finish()?;
+        let mut data = writer.into_inner()?;
+        assert!(new_reader(data.clone()).read_metadata()?.utf8);
+
+        let plain_position = data.windows(3).position(|window| window == b"lol").unwrap();
+        data[plain_position] = 0xff;
+        let report = new_reader(data).verify()?;
+        assert!(report.problems.iter().any(|problem| matches!(
+            problem,
+            Problem::InvalidPlaintext {
+                chunk_index: 2,
+                entry_number: 0
+            }
+        )));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_negotiates_format_versions() -> Result<(), BdfError> {
+        let v1 = create_numbered_file(5, 2, false)?;
+        assert_eq!(&v1[..HEADER_SIZE], BDF_HDR);
+        assert_eq!(new_reader(v1.clone()).format_version()?, FormatVersion::V1);
+
+        let writer = BDFWriterBuilder::new()
+            .format_version(FormatVersion::V2)
+            .build(Vec::new())?;
+        let v2 = write_numbered(writer, 5, 2)?;
+        assert_eq!(v2[3], 2);
+        let mut reader = new_reader(v2.clone());
+        assert_eq!(reader.format_version()?, FormatVersion::V2);
+        assert_eq!(reader.entries().count(), 5);
+
+        let writer = BDFWriterBuilder::new()
+            .write_index(true)
+            .build(Vec::new())?;
+        let indexed = write_numbered(writer, 5, 2)?;
+        assert_eq!(indexed[3], 2);
+        assert_eq!(new_reader(indexed).read_chunk_index()?.entries.len(), 3);
+        assert!(matches!(
+            BDFWriterBuilder::new()
+                .format_version(FormatVersion::V1)
+                .write_index(true)
+                .build(Vec::new()),
+            Err(BdfError::InvalidConfig(_))
+        ));
+        let mut writer = BDFWriter::new(Vec::new(), 0, false);
+        writer.set_format_version(FormatVersion::V1)?;
+        writer.set_write_stats(true)?;
+        assert!(matches!(writer.finish(), Err(BdfError::InvalidConfig(_))));
+        assert!(matches!(
+            BDFWriterBuilder::new()
+                .format_version(FormatVersion::V1)
+                .build(Vec::new()),
+            Err(BdfError::InvalidConfig(_))
+        ));
+
+        // the trailing META chunk and custom critical chunks need version 2 as well
+        let streaming = write_numbered(BDFWriter::new_streaming(Vec::new(), false), 5, 2)?;
+        assert_eq!(streaming[3], 2);
+        let mut writer = BDFWriter::new(Vec::new(), 5, false);
+        writer.add_chunk(&NoteChunk(ChunkId::new(*b"NOTE"), Vec::new()))?;
+        assert_eq!(write_numbered(writer, 5, 2)?[3], 2);
+
+        // options adding chunks can't be enabled after the version 1 header was written
+        let mut writer = numbered_writer(5, 2, false)?;
+        assert!(matches!(
+            writer.set_write_index(true),
+            Err(BdfError::HeadAlreadyWritten)
+        ));
+        assert!(matches!(
+            writer.set_write_stats(true),
+            Err(BdfError::HeadAlreadyWritten)
+        ));
+        assert!(matches!(
+            writer.set_bloom_filter(FOO, 0.01),
+            Err(BdfError::HeadAlreadyWritten)
+        ));
+        writer.finish()?;
+        assert_eq!(writer.into_inner()?[3], 1);
+
+        let mut v3 = v2;
+        v3[3] = 3;
+        assert!(matches!(
+            new_reader(v3.clone()).read_metadata(),
+            Err(BdfError::UnsupportedVersion(3))
+        ));
+        let report = new_reader(v3).verify()?;
+        assert!(matches!(
+            report.problems[..],
+            [Problem::UnsupportedVersion(3)]
+        ));
+
+        let mut wrong_magic = v1.clone();
+        wrong_magic[0] = b'X';
+        assert!(matches!(
+            new_reader(wrong_magic).read_metadata(),
+            Err(BdfError::InvalidHeader)
+        ));
+        assert!(matches!(
+            new_reader(v1[..5].to_vec()).read_metadata(),
+            Err(BdfError::Truncated)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_64_bit_chunk_lengths() -> Result<(), BdfError> {
+        let writer = BDFWriterBuilder::new()
+            .format_version(FormatVersion::V2)
+            .compression_method("lzma")
+            .write_index(true)
+            .build(Vec::new())?;
+        let data = write_numbered(writer, 10, 3)?;
+        let mut length_raw = [0u8; 8];
+        length_raw.copy_from_slice(&data[HEADER_SIZE..HEADER_SIZE + 8]);
+        let meta_length = u64::from_be_bytes(length_raw);
+        assert_eq!(
+            &data[HEADER_SIZE + 8..HEADER_SIZE + 12],
+            META_CHUNK_NAME.as_bytes()
+        );
+
+        let mut reader = new_reader(data.clone());
+        assert_eq!(
+            reader.read_metadata()?.serialize().len() as u64,
+            meta_length
+        );
+        assert_eq!(reader.read_chunk_index()?.entries.len(), 4);
+        assert_eq!(reader.seek_entry(7)?.plain_str(), Ok("7"));
+        assert_eq!(reader.entries().count(), 10);
+        assert!(new_reader(data.clone()).verify()?.problems.is_empty());
+
+        let mut truncated = data;
+        truncated.truncate(truncated.len() - 40);
+        let mut reader = new_reader(truncated);
+        let mut salvage = reader.salvage();
+        assert_eq!(salvage.by_ref().filter(|entry| entry.is_ok()).count(), 10);
+        assert_eq!(salvage.report().chunks_recovered, 4);
+
+        let mut oversized = GenericChunk::new(ChunkId::DTBL, Vec::new());
+        oversized.length = u32::MAX as u64 + 1;
+        assert!(matches!(
+            oversized.serialize(FormatVersion::V1),
+            Err(BdfError::TooLarge { what: "chunk", .. })
+        ));
+        assert!(oversized.serialize(FormatVersion::V2).is_ok());
+
+        let index = ChunkIndex {
+            entries: vec![ChunkIndexEntry {
+                offset: 11,
+                length: u32::MAX as u64 + 1,
+                entry_count: 1,
+            }],
+        };
+        assert!(matches!(
+            index.serialize(FormatVersion::V1),
+            Err(BdfError::TooLarge { .. })
+        ));
+        let chunk = GenericChunk::new(ChunkId::CIDX, index.serialize(FormatVersion::V2)?);
+        assert_eq!(
+            ChunkIndex::parse(chunk, FormatVersion::V2)?.entries,
+            index.entries
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_skips_unknown_ancillary_chunks() -> Result<(), BdfError> {
+        let critical = ChunkId::new(*b"NOTE");
+        let ancillary = ChunkId::new(*b"note");
+        assert!(critical.is_critical() && ancillary.is_ancillary());
+        assert!(ChunkId::BUILTIN
+            .iter()
+            .all(|id| id.is_critical() || *id == ChunkId::STAT));
+
+        let mut writer = BDFWriter::new(Vec::new(), 5, true);
+        writer.add_chunk(&NoteChunk(ancillary, b"skip me".to_vec()))?;
+        let data = write_numbered(writer, 5, 2)?;
+        let mut reader = new_reader(data.clone());
+        assert_eq!(reader.entries().count(), 5);
+        assert!(new_reader(data).verify()?.problems.is_empty());
+
+        let mut writer = BDFWriter::new(Vec::new(), 5, true);
+        writer.add_chunk(&NoteChunk(critical, b"read me".to_vec()))?;
+        let data = write_numbered(writer, 5, 2)?;
+        let mut reader = new_reader(data.clone());
+        assert!(matches!(
+            reader.entries().next(),
+            Some(Err(BdfError::UnknownChunk(id))) if id == critical
+        ));
+        let report = new_reader(data.clone()).verify()?;
+        assert!(matches!(
+            &report.problems[..],
+            [Problem::InvalidChunk {
+                error: BdfError::UnknownChunk(_),
+                ..
+            }]
+        ));
+
+        let mut reader = BDFReaderBuilder::new()
+            .chunk_decoder(Arc::new(NoteDecoder(critical)))
+            .build(Cursor::new(data))?;
+        reader.read_start()?;
+        let chunk = reader.next_chunk()?.unwrap();
+        assert_eq!(chunk.id(), critical);
+        let note = reader.decode_chunk(&chunk)?.unwrap();
+        assert_eq!(
+            note.as_any().downcast_ref::<NoteChunk>(),
+            Some(&NoteChunk(critical, b"read me".to_vec()))
+        );
+        assert_eq!(reader.entries().count(), 5);
+
+        let mut writer = BDFWriter::new(Vec::new(), 0, false);
+        assert!(matches!(
+            writer.add_chunk(&NoteChunk(ChunkId::DTBL, Vec::new())),
+            Err(BdfError::InvalidChunkId(_))
+        ));
+        assert!(matches!(
+            writer.add_chunk(&NoteChunk(ChunkId::new(*b"N0TE"), Vec::new())),
+            Err(BdfError::InvalidChunkId(_))
+        ));
+        writer.finish()?;
+        assert!(matches!(
+            writer.add_chunk(&NoteChunk(critical, Vec::new())),
+            Err(BdfError::HeadAlreadyWritten)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_stores_info_values() -> Result<(), BdfError> {
+        let mut writer = BDFWriter::new(Vec::new(), 5, true);
+        writer.set_info("source", "rockyou.txt")?;
+        writer.set_info("created", 1_700_000_000i64)?;
+        writer.set_info("ratio", 0.5)?;
+        writer.set_info("lowercase", true)?;
+        writer.set_info("salt", vec![0u8, 255])?;
+        writer.add_chunk(&NoteChunk(ChunkId::new(*b"note"), Vec::new()))?;
+        let data = write_numbered(writer, 5, 2)?;
+
+        let mut reader = new_reader(data.clone());
+        let info = reader.info()?.unwrap().clone();
+        assert_eq!(info.entries.len(), 5);
+        assert_eq!(
+            info.get("source").and_then(InfoValue::as_str),
+            Some("rockyou.txt")
+        );
+        assert_eq!(
+            info.get("created").and_then(InfoValue::as_integer),
+            Some(1_700_000_000)
+        );
+        assert_eq!(info.get("ratio").and_then(InfoValue::as_float), Some(0.5));
+        assert_eq!(
+            info.get("lowercase").and_then(InfoValue::as_bool),
+            Some(true)
+        );
+        assert_eq!(
+            info.get("salt").and_then(InfoValue::as_bytes),
+            Some(&[0u8, 255][..])
+        );
+        assert_eq!(reader.entries().count(), 5);
+        assert!(new_reader(data).verify()?.problems.is_empty());
+
+        let writer = BDFWriterBuilder::new()
+            .info("generator", "bdflib")
+            .build_seekable(Cursor::new(Vec::new()))?;
+        let data = write_numbered(writer, 3, 2)?.into_inner();
+        let mut reader = new_reader(data);
+        assert_eq!(
+            reader.info()?.and_then(|info| info.get("generator")),
+            Some(&InfoValue::Text("bdflib".to_string()))
+        );
+        let metadata = reader.metadata.clone().unwrap();
+        assert_eq!((metadata.chunk_count, metadata.entry_count), (2, 3));
+
+        let mut reader = new_reader(create_numbered_file(3, 2, false)?);
+        assert!(reader.info()?.is_none());
+
+        let mut writer = BDFWriter::new(Vec::new(), 0, false);
+        writer.finish()?;
+        assert!(matches!(
+            writer.set_info("late", "value"),
+            Err(BdfError::HeadAlreadyWritten)
+        ));
+
+        let mut info = InfoChunk::new();
+        info.insert("flag", true);
+        let mut data = info.serialize();
+        let last = data.len() - 1;
+        data[last] = 2;
+        assert!(matches!(
+            InfoChunk::try_from(GenericChunk::new(ChunkId::INFO, data)),
+            Err(BdfError::InvalidInfoValue(key)) if key == "flag"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_crc_mismatches() -> Result<(), BdfError> {
+        let mut data = create_simple_file(true)?;
+        let crc_position = data.len() - 1;
+        data[crc_position] ^= 0xff;
+
+        let mut reader = new_reader(data);
+        reader.read_start()?;
+
+        match reader.next_chunk() {
+            Err(BdfError::CrcMismatch { chunk_index, .. }) => assert_eq!(chunk_index, 2),
+            other => panic!("expected a crc mismatch, got {:?}", other),
+        }
 
-        remove_file("tmp4.bdf")?;
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_unknown_hash_ids() {
+        let mut lookup_table = HashLookupTable::new(HashMap::new());
+        lookup_table.insert(0, HashEntry::new(FOO.to_string(), 4));
+        let mut entry = DataEntry::new("lol".to_string());
+        entry.add_hash(0, &[2, 4, 0, 2]);
+        let mut chunk = GenericChunk::from_data_entries(&[entry], &lookup_table);
+        let empty_table = HashLookupTable::new(HashMap::new());
+
+        match chunk.data_entries(&empty_table) {
+            Err(BdfError::UnknownHashId(0)) => {}
+            other => panic!("expected an unknown hash id, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_stores_hash_values_by_id() -> Result<(), BdfError> {
+        let mut lookup_table = HashLookupTable::new(HashMap::new());
+        lookup_table.insert(0, HashEntry::new(FOO.to_string(), 4));
+        lookup_table.insert(1, HashEntry::new(BAR.to_string(), 2));
+        assert_eq!(lookup_table.get_id(BAR), Some(1));
+        assert_eq!(lookup_table.get_id("baz"), None);
+
+        let mut entry = DataEntry::new("lol");
+        entry.add_hash_value(&lookup_table, BAR, &[1, 2])?;
+        entry.add_hash(0, &[3, 4, 5, 6]);
+        entry.add_hash(0, &[7, 8, 9, 0]);
+        assert!(matches!(
+            entry.add_hash_value(&lookup_table, "baz", &[1]),
+            Err(BdfError::UnknownHashName(_))
+        ));
+        let hashes: Vec<(u32, &[u8])> = entry.hashes().collect();
+        assert_eq!(hashes, vec![(0, &[7, 8, 9, 0][..]), (1, &[1, 2][..])]);
+        assert_eq!(
+            entry.get_hash_value(&lookup_table, FOO),
+            Some(&[7, 8, 9, 0][..])
+        );
+
+        let decoded = DataEntry::deserialize(&entry.serialize(&lookup_table), &lookup_table)?;
+        assert_eq!(decoded.plain, entry.plain);
+        assert!(decoded.hashes().eq(entry.hashes()));
+
+        // replacing values with ones of another length keeps the other values intact
+        entry.add_hash(1, &[1, 2, 3]);
+        entry.add_hash(0, &[4, 5]);
+        entry.add_hash(1, &[6]);
+        let hashes: Vec<(u32, &[u8])> = entry.hashes().collect();
+        assert_eq!(hashes, vec![(0, &[4, 5][..]), (1, &[6][..])]);
+        assert_eq!(entry.serialize(&lookup_table).len(), 8 + 3 + 4 + 2 + 4 + 1);
+
+        lookup_table
+            .entries
+            .insert(2, HashEntry::new("baz".to_string(), 1));
+        assert_eq!(lookup_table.get_id("baz"), Some(2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_borrows_entries_from_chunks() -> Result<(), BdfError> {
+        let mut reader = new_reader(create_numbered_file(10, 4, true)?);
+        reader.read_start()?;
+        let lookup_table = reader.lookup_table.clone().unwrap();
+        let foo_id = *lookup_table.get_entry(FOO).unwrap().0;
+        let mut chunk = reader.next_chunk()?.expect("missing data chunk");
+
+        let entries = chunk
+            .entry_refs(&lookup_table)?
+            .collect::<Result<Vec<DataEntryRef>, BdfError>>()?;
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[2].plain_str(), Ok("2"));
+        assert_eq!(entries[2].hash_value(foo_id), Some(&2u32.to_be_bytes()[..]));
+        assert_eq!(entries[2].hash_value(foo_id + 1), None);
+        assert_eq!(entries[2].hash_values().count(), 1);
+        let owned: Vec<DataEntry> = entries.iter().map(DataEntryRef::to_data_entry).collect();
+        let decoded = chunk.clone().data_entries(&lookup_table)?;
+        for (owned, decoded) in owned.iter().zip(&decoded) {
+            assert_eq!(owned.plain, decoded.plain);
+            assert_eq!(owned.hash(foo_id), decoded.hash(foo_id));
+            assert_eq!(
+                owned.get_hash_value(&lookup_table, FOO),
+                decoded.get_hash_value(&lookup_table, FOO)
+            );
+        }
+
+        let empty_table = HashLookupTable::new(HashMap::new());
+        let results: Vec<_> = chunk.entry_refs(&empty_table)?.collect();
+        assert!(matches!(results[..], [Err(BdfError::UnknownHashId(_))]));
+        chunk.data.truncate(chunk.data.len() - 2);
+        let results: Vec<_> = chunk.entry_refs(&lookup_table)?.collect();
+        assert_eq!(results.len(), 4);
+        assert!(matches!(results[3], Err(BdfError::Truncated)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_iterates_entries() -> Result<(), BdfError> {
+        for compressed in [false, true] {
+            let mut reader = new_reader(create_numbered_file(10, 3, compressed)?);
+            let entries = reader
+                .entries()
+                .collect::<Result<Vec<DataEntry>, BdfError>>()?;
+            let plains: Vec<Vec<u8>> = entries.into_iter().map(|entry| entry.plain).collect();
+            let expected: Vec<Vec<u8>> = (0..10).map(|i| i.to_string().into_bytes()).collect();
+            assert_eq!(plains, expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_truncated_files() -> Result<(), BdfError> {
+        let mut data = create_numbered_file(10, 3, true)?;
+        data.truncate(data.len() - 6);
+        let mut reader = new_reader(data);
+        let results: Vec<Result<DataEntry, BdfError>> = reader.entries().collect();
+
+        assert_eq!(results.len(), 10);
+        assert!(results[..9].iter().all(|result| result.is_ok()));
+        assert!(matches!(results[9], Err(BdfError::Truncated)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_seeks_chunks_and_entries() -> Result<(), BdfError> {
+        for compressed in [false, true] {
+            let mut writer = BDFWriter::new(Vec::new(), 10, compressed);
+            writer.set_write_index(true)?;
+            add_numbered_entries(&mut writer, 10, 3)?;
+            writer.finish()?;
+            writer.finish()?;
+            let data = writer.into_inner()?;
+            assert_eq!(data.windows(4).filter(|name| *name == b"CFTR").count(), 1);
+            let mut reader = new_reader(data);
+
+            assert_eq!(reader.seek_entry(7)?.plain_str(), Ok("7"));
+            let lookup_table = reader.lookup_table.clone().unwrap();
+            let mut chunk = reader.seek_chunk(1)?;
+            assert_eq!(chunk.data_entries(&lookup_table)?[0].plain_str(), Ok("3"));
+            assert!(matches!(
+                reader.seek_entry(10),
+                Err(BdfError::OutOfRange(10))
+            ));
+            assert_eq!(reader.entries().count(), 10);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rebuilds_missing_chunk_indices() -> Result<(), BdfError> {
+        let mut reader = new_reader(create_numbered_file(10, 3, true)?);
+        let counts: Vec<u32> = reader
+            .read_chunk_index()?
+            .entries
+            .iter()
+            .map(|entry| entry.entry_count)
+            .collect();
+
+        assert_eq!(counts, vec![3, 3, 3, 1]);
+        assert_eq!(reader.seek_entry(9)?.plain_str(), Ok("9"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_finds_entries_by_hash() -> Result<(), BdfError> {
+        for compressed in [false, true] {
+            let mut reader = new_reader(create_numbered_file(10, 3, compressed)?);
+            let matches = reader.find_by_hash(FOO, &7u32.to_be_bytes())?;
+
+            assert_eq!(matches.len(), 1);
+            assert_eq!(matches[0].entry.plain_str(), Ok("7"));
+            assert_eq!(matches[0].chunk_number, 2);
+            assert_eq!(matches[0].entry_number, 1);
+            assert!(reader.find_by_hash(FOO, &3u32.to_be_bytes())?.is_empty());
+
+            let data = create_numbered_file(10, 3, compressed)?;
+            let mut reader = BDFReader::new_seekable(Cursor::new(data));
+            for i in [7u32, 3, 7] {
+                let matches = reader.find_by_hash(FOO, &i.to_be_bytes())?;
+                assert_eq!(matches.len(), 1);
+                assert_eq!(matches[0].entry.plain_str(), Ok(i.to_string().as_str()));
+            }
+            assert_eq!(reader.entries().count(), 10);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_finds_batches_of_hashes() -> Result<(), BdfError> {
+        let mut reader = new_reader(create_numbered_file(10, 3, true)?);
+        let targets: HashSet<Vec<u8>> = [2u32, 9, 100]
+            .iter()
+            .map(|i| i.to_be_bytes().to_vec())
+            .collect();
+        let matches = reader.find_by_hashes(FOO, &targets)?;
+        let plains: Vec<&str> = matches
+            .iter()
+            .map(|m| m.entry.plain_str().unwrap())
+            .collect();
+
+        assert_eq!(plains, vec!["2", "9"]);
+        assert!(matches!(
+            reader.find_by_hash(BAR, &[0]),
+            Err(BdfError::UnknownHashName(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_sorts_entries_by_hash() -> Result<(), BdfError> {
+        for compressed in [false, true] {
+            let mut writer = BDFWriter::new(Vec::new(), 20, compressed);
+            writer.set_entries_per_chunk(3)?;
+            let foo = writer.add_lookup_entry(HashEntry::new(FOO.to_string(), 4))?;
+            writer.set_sort_by(FOO)?;
+            writer.set_sort_buffer_size(4);
+            for i in (0..20u32).rev() {
+                let mut entry = DataEntry::new(i.to_string());
+                entry.add_hash(foo, &i.to_be_bytes());
+                writer.add_data_entry(entry)?;
+            }
+            writer.finish()?;
+            let data = writer.into_inner()?;
+
+            let mut reader = new_reader(data.clone());
+            let sort_index = reader.read_sort_index()?.expect("missing sort index");
+            assert_eq!(sort_index.ranges.len(), 7);
+            assert_eq!(sort_index.candidate_chunks(&13u32.to_be_bytes()), vec![4]);
+            assert_eq!(sort_index.candidate_chunks(&20u32.to_be_bytes()), vec![]);
+
+            let matches = reader.find_by_hash_sorted(FOO, &13u32.to_be_bytes())?;
+            assert_eq!(matches.len(), 1);
+            assert_eq!(matches[0].entry.plain_str(), Ok("13"));
+
+            // only the chunk containing the hash is read, so damage elsewhere doesn't matter
+            let mut damaged = data.clone();
+            let offset = new_reader(data.clone()).read_chunk_index()?.entries[0].offset;
+            damaged[offset as usize + 10] ^= 0xFF;
+            let matches = new_reader(damaged).find_by_hash_sorted(FOO, &13u32.to_be_bytes())?;
+            assert_eq!(matches.len(), 1);
+
+            let plains: Vec<String> = reader
+                .entries()
+                .map(|entry| entry.and_then(plain_string))
+                .collect::<Result<Vec<String>, BdfError>>()?;
+            let expected: Vec<String> = (0..20).map(|i| i.to_string()).collect();
+            assert_eq!(plains, expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_index_entries_past_the_end() -> Result<(), BdfError> {
+        let mut writer = BDFWriter::new(Vec::new(), 20, false);
+        writer.set_entries_per_chunk(3)?;
+        let foo = writer.add_lookup_entry(HashEntry::new(FOO.to_string(), 4))?;
+        writer.set_sort_by(FOO)?;
+        for i in 0..20u32 {
+            let mut entry = DataEntry::new(i.to_string());
+            entry.add_hash(foo, &i.to_be_bytes());
+            writer.add_data_entry(entry)?;
+        }
+        writer.finish()?;
+        let mut data = writer.into_inner()?;
+
+        let mut index = new_reader(data.clone()).read_chunk_index()?.clone();
+        index.entries.last_mut().unwrap().offset = u64::MAX;
+        let mut chunk = GenericChunk::new(ChunkId::CIDX, index.serialize(FormatVersion::V2)?);
+        let serialized = chunk.serialize(FormatVersion::V2)?;
+        let start = data.windows(4).rposition(|name| name == b"CIDX").unwrap() - 8;
+        data[start..start + serialized.len()].copy_from_slice(&serialized);
+        assert!(matches!(
+            new_reader(data).read_sort_index(),
+            Err(BdfError::Truncated)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_skips_chunks_ruled_out_by_bloom_filters() -> Result<(), BdfError> {
+        for bloom_filter in [false, true] {
+            let mut writer = BDFWriter::new(Vec::new(), 30, true);
+            writer.set_entries_per_chunk(3)?;
+            writer.set_write_index(true)?;
+            let foo = writer.add_lookup_entry(HashEntry::new(FOO.to_string(), 4))?;
+            if bloom_filter {
+                writer.set_bloom_filter(FOO, 0.01)?;
+            }
+            for i in 0..30u32 {
+                let mut entry = DataEntry::new(i.to_string());
+                entry.add_hash(foo, &i.to_be_bytes());
+                writer.add_data_entry(entry)?;
+            }
+            writer.finish()?;
+            let mut data = writer.into_inner()?;
+
+            let offset = new_reader(data.clone()).read_chunk_index()?.entries[0].offset;
+            data[offset as usize + 14] ^= 0xFF;
+
+            let result = new_reader(data).find_by_hash(FOO, &7u32.to_be_bytes());
+            if bloom_filter {
+                let matches = result?;
+                assert_eq!(matches.len(), 1);
+                assert_eq!(matches[0].entry.plain_str(), Ok("7"));
+            } else {
+                assert!(result.is_err());
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_ignores_invalid_bloom_filters() -> Result<(), BdfError> {
+        let mut data = 0u32.to_be_bytes().to_vec();
+        data.extend_from_slice(&u32::MAX.to_be_bytes());
+        data.extend_from_slice(&[0u8; 8]);
+        assert!(matches!(
+            BloomFilter::try_from(GenericChunk::new(ChunkId::BLOM, data)),
+            Err(BdfError::InvalidBloomFilter(u32::MAX))
+        ));
+        let mut data = 0u32.to_be_bytes().to_vec();
+        data.extend_from_slice(&1u32.to_be_bytes());
+        let filter = BloomFilter::try_from(GenericChunk::new(ChunkId::BLOM, data))?;
+        assert!(filter.contains(b"anything"));
+
+        let mut writer = BDFWriter::new(Vec::new(), 9, false);
+        writer.set_entries_per_chunk(3)?;
+        let foo = writer.add_lookup_entry(HashEntry::new(FOO.to_string(), 4))?;
+        for rate in [f64::NAN, -1.0, 0.0, 1.0] {
+            assert!(matches!(
+                writer.set_bloom_filter(FOO, rate),
+                Err(BdfError::InvalidConfig(_))
+            ));
+            assert!(matches!(
+                BloomFilter::new(foo, 3, rate),
+                Err(BdfError::InvalidConfig(_))
+            ));
+        }
+        writer.set_bloom_filter(FOO, 0.01)?;
+        for i in 0..9u32 {
+            let mut entry = DataEntry::new(i.to_string());
+            entry.add_hash(foo, &i.to_be_bytes());
+            writer.add_data_entry(entry)?;
+        }
+        writer.finish()?;
+        let mut data = writer.into_inner()?;
+
+        // clearing the bits of the first filter without updating its crc sum
+        let id = data
+            .windows(4)
+            .position(|window| window == b"BLOM")
+            .unwrap();
+        let length = u32::from_be_bytes(<[u8; 4]>::try_from(&data[id - 4..id]).unwrap()) as usize;
+        data[id + 12..id + 4 + length].fill(0);
+        let matches = new_reader(data).find_by_hash(FOO, &1u32.to_be_bytes())?;
+        assert_eq!(matches.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_chunk_stats() -> Result<(), BdfError> {
+        let writer = BDFWriterBuilder::new()
+            .write_stats(true)
+            .write_index(true)
+            .build(Vec::new())?;
+        let mut data = write_numbered(writer, 25, 10)?;
+
+        let stats = new_reader(data.clone()).read_chunk_stats()?;
+        assert_eq!(
+            stats.iter().map(|(number, _)| *number).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+        let (_, first) = &stats[0];
+        assert_eq!(first.entry_count, 10);
+        assert_eq!((first.min_plain_length, first.max_plain_length), (1, 1));
+        assert!(first.uncompressed_size > 0);
+        let range = first.hash_range(0).unwrap();
+        assert_eq!(range.min, 0u32.to_be_bytes());
+        assert_eq!(range.max, 9u32.to_be_bytes());
+        assert_eq!(stats[2].1.entry_count, 5);
+        assert!(new_reader(data.clone()).verify()?.problems.is_empty());
+
+        let mut reader = new_reader(data.clone());
+        reader.set_stats_filter(Some(StatsFilter {
+            plain_length: Some(2..=2),
+            hash_range: None,
+        }));
+        assert_eq!(reader.entries().count(), 15);
+
+        let offset = new_reader(data.clone()).read_chunk_index()?.entries[0].offset;
+        data[offset as usize + 14] ^= 0xFF;
+        let matches = new_reader(data).find_by_hash(FOO, &15u32.to_be_bytes())?;
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].entry.plain_str(), Ok("15"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_verifies_files() -> Result<(), BdfError> {
+        for compressed in [false, true] {
+            let report = new_reader(create_numbered_file(10, 3, compressed)?).verify()?;
+            assert!(report.is_ok(), "{:?}", report.problems);
+            assert_eq!(report.chunks_checked, 6);
+            assert_eq!(report.data_chunks, 4);
+            assert_eq!(report.entries, 10);
+        }
+        let streamed = write_numbered(BDFWriter::new_streaming(Vec::new(), true), 10, 3)?;
+        assert!(new_reader(streamed).verify()?.is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_verification_problems() -> Result<(), BdfError> {
+        let mut data = create_numbered_file(10, 3, false)?;
+        // the last byte of the output length in the lookup table
+        data[58] = 5;
+        data.truncate(data.len() - 2);
+        let report = new_reader(data).verify()?;
+
+        assert!(matches!(
+            report.problems[0],
+            Problem::CrcMismatch { chunk_index: 1, .. }
+        ));
+        let length_mismatches = report
+            .problems
+            .iter()
+            .filter(|problem| matches!(problem, Problem::HashLengthMismatch { .. }))
+            .count();
+        assert_eq!(length_mismatches, 9);
+        assert!(matches!(report.problems[10], Problem::Truncated { .. }));
+        assert!(matches!(
+            report.problems[11],
+            Problem::ChunkCountMismatch {
+                expected: 4,
+                actual: 3
+            }
+        ));
 
         Ok(())
     }
 
-    fn create_simple_file(name: &str, compressed: bool) -> Result<(), Error> {
-        let mut writer = new_writer(name, 1, compressed)?;
+    #[test]
+    fn it_salvages_damaged_chunks() -> Result<(), BdfError> {
+        for compressed in [false, true] {
+            let clean = create_numbered_file(10, 3, compressed)?;
+            let index = new_reader(clean.clone()).read_chunk_index()?.clone();
+            let (second, third) = (index.entries[1].offset, index.entries[2].offset);
+
+            let mut damaged_data = clean.clone();
+            damaged_data[second as usize + 10] ^= 0xFF;
+            let mut damaged_length = clean;
+            damaged_length[second as usize] = 0xFF;
+
+            for data in [damaged_data, damaged_length] {
+                let mut reader = new_reader(data);
+                let mut salvage = reader.salvage();
+                let plains = salvage
+                    .by_ref()
+                    .map(|entry| entry.and_then(plain_string))
+                    .collect::<Result<Vec<String>, BdfError>>()?;
+                assert_eq!(plains, vec!["0", "1", "2", "6", "7", "8", "9"]);
 
+                let report = salvage.into_report();
+                assert_eq!(report.chunks_recovered, 3);
+                assert_eq!(report.losses.len(), 1);
+                match &report.losses[0] {
+                    Loss::DamagedChunk { offset, length, .. } => {
+                        assert_eq!(*offset, second);
+                        assert_eq!(*length, third - second);
+                    }
+                    other => panic!("expected a damaged chunk, got {:?}", other),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_salvages_chunks_with_overflowing_lengths() -> Result<(), BdfError> {
+        let writer = BDFWriterBuilder::new()
+            .format_version(FormatVersion::V2)
+            .build(Vec::new())?;
+        let mut data = write_numbered(writer, 10, 3)?;
+        let second = new_reader(data.clone()).read_chunk_index()?.entries[1].offset as usize;
+        data[second..second + 8].fill(0xFF);
+
+        let plains = new_reader(data)
+            .salvage()
+            .map(|entry| entry.and_then(plain_string))
+            .collect::<Result<Vec<String>, BdfError>>()?;
+        assert_eq!(plains, vec!["0", "1", "2", "6", "7", "8", "9"]);
+
+        // a last chunk that claims almost all of u64 is salvaged as truncated
+        let writer = BDFWriterBuilder::new()
+            .entry_count(10)
+            .format_version(FormatVersion::V2)
+            .build(Vec::new())?;
+        let mut data = write_numbered(writer, 10, 3)?;
+        let last = new_reader(data.clone()).read_chunk_index()?.entries[3].offset as usize;
+        data[last..last + 8].copy_from_slice(&(u64::MAX - 1).to_be_bytes());
+        let mut reader = new_reader(data);
+        reader.set_limits(ReadLimits {
+            max_chunk_size: u64::MAX,
+            ..ReadLimits::default()
+        });
+        let mut salvage = reader.salvage();
+        assert_eq!(salvage.by_ref().filter(|entry| entry.is_ok()).count(), 10);
+        match &salvage.report().losses[..] {
+            [Loss::TruncatedChunk { missing, .. }] => assert!(*missing > u32::MAX as u64),
+            other => panic!("expected a truncated chunk, got {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_salvages_without_rereading_the_file() -> Result<(), BdfError> {
+        let mut writer = BDFWriter::new(Vec::new(), 10_000, false);
+        writer.set_entries_per_chunk(1000)?;
         writer.add_lookup_entry(HashEntry::new(FOO.to_string(), 4))?;
+        for _ in 0..10_000 {
+            writer.add_data_entry(DataEntry::new(format!("1111{}", "abcd".repeat(8))))?;
+        }
+        writer.finish()?;
+        let mut data = writer.into_inner()?;
+        let offset = new_reader(data.clone()).read_chunk_index()?.entries[0].offset;
+        data[offset as usize + 20] ^= 0xFF;
+        let length = data.len() as u64;
+
+        // every "abcd" in the damaged chunk looks like the header of a chunk running past the end
+        let bytes_read = Rc::new(Cell::new(0));
+        let mut reader = BDFReader::new(CountingReader {
+            inner: Cursor::new(data),
+            bytes_read: Rc::clone(&bytes_read),
+        });
+        assert_eq!(reader.salvage().count(), 9000);
+        let bytes_read = bytes_read.get();
+        assert!(bytes_read < 3 * length, "{} of {}", bytes_read, length);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_salvages_truncated_chunks() -> Result<(), BdfError> {
+        for compressed in [false, true] {
+            let mut data = create_numbered_file(10, 4, compressed)?;
+            data.truncate(data.len() - 7);
+            let mut reader = new_reader(data);
+            let mut salvage = reader.salvage();
+            let count = salvage
+                .by_ref()
+                .collect::<Result<Vec<_>, BdfError>>()?
+                .len();
+            let report = salvage.into_report();
+
+            assert_eq!(report.chunks_recovered, 2);
+            assert_eq!(report.entries_recovered, count as u64);
+            match &report.losses[..] {
+                [Loss::TruncatedChunk {
+                    missing,
+                    entries_recovered,
+                    ..
+                }] => {
+                    assert_eq!(*missing, 7);
+                    assert_eq!(count, 8 + *entries_recovered as usize);
+                    if !compressed {
+                        assert_eq!(*entries_recovered, 1);
+                    }
+                }
+                other => panic!("expected a truncated chunk, got {:?}", other),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_trailing_bytes() -> Result<(), BdfError> {
+        let mut lookup_table = HashLookupTable::new(HashMap::new());
+        for (id, name) in [FOO, BAR].iter().enumerate() {
+            let mut entry = HashEntry::new(name.to_string(), 4);
+            entry.id = id as u32;
+            lookup_table.entries.insert(id as u32, entry);
+        }
+        let mut entry = DataEntry::new("lol".to_string());
+        entry.add_hash(0, &[1, 2, 3, 4]);
+        let mut chunk = GenericChunk::from_data_entries(&[entry], &lookup_table);
+        assert_eq!(chunk.entry_count()?, 1);
+        chunk.data.extend_from_slice(&[0, 0, 0]);
+        assert!(matches!(chunk.entry_count(), Err(BdfError::Truncated)));
+        assert!(chunk.entry_refs(&lookup_table)?.last().unwrap().is_err());
+
+        let mut chunk = GenericChunk::from(&lookup_table);
+        assert!(HashLookupTable::try_from(chunk.clone()).is_ok());
+        assert!(matches!(
+            HashLookupTable::parse_limited(chunk.clone(), 1),
+            Err(BdfError::LimitExceeded { size: 2, .. })
+        ));
+        chunk.data.extend_from_slice(&[0; 5]);
+        assert!(matches!(
+            HashLookupTable::try_from(chunk),
+            Err(BdfError::Truncated)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_enforces_read_limits() -> Result<(), BdfError> {
+        let data = create_numbered_file(10, 3, true)?;
+        let limits = [
+            ReadLimits {
+                max_chunk_size: 30,
+                ..ReadLimits::default()
+            },
+            ReadLimits {
+                max_entry_size: 5,
+                ..ReadLimits::default()
+            },
+            ReadLimits {
+                max_hash_count: 0,
+                ..ReadLimits::default()
+            },
+        ];
+        for limits in limits.iter() {
+            let mut reader = new_reader(data.clone());
+            reader.set_limits(*limits);
+            let result = reader
+                .entries()
+                .collect::<Result<Vec<DataEntry>, BdfError>>();
+            assert!(matches!(result, Err(BdfError::LimitExceeded { .. })));
+        }
+
+        let mut huge_chunk = BDF_HDR.to_vec();
+        huge_chunk.extend_from_slice(&0xFFFF_FFF0u32.to_be_bytes());
+        huge_chunk.extend_from_slice(META_CHUNK_NAME.as_bytes());
+        assert!(matches!(
+            new_reader(huge_chunk.clone()).read_metadata(),
+            Err(BdfError::LimitExceeded { .. })
+        ));
+        let mut reader = new_reader(huge_chunk);
+        reader.set_limits(ReadLimits::unlimited());
+        assert!(matches!(reader.read_metadata(), Err(BdfError::Truncated)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_corrupted_files_without_panicking() -> Result<(), BdfError> {
+        for compressed in [false, true] {
+            let data = create_numbered_file(4, 2, compressed)?;
+            for position in 0..data.len() {
+                for value in [0x00, 0x7F, 0xFF] {
+                    let mut corrupted = data.clone();
+                    corrupted[position] = value;
+                    let _ = new_reader(corrupted.clone()).entries().count();
+                    let _ = new_reader(corrupted.clone()).verify()?;
+                    let _ = new_reader(corrupted).salvage().count();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn create_simple_file(compressed: bool) -> Result<Vec<u8>, BdfError> {
+        let mut writer = BDFWriter::new(Vec::new(), 1, compressed);
+
+        let foo = writer.add_lookup_entry(HashEntry::new(FOO.to_string(), 4))?;
         let mut entry_1 = DataEntry::new("lol".to_string());
-        entry_1.add_hash_value(FOO.to_string(), vec![2, 4, 0, 2]);
+        entry_1.add_hash(foo, &[2, 4, 0, 2]);
         writer.add_data_entry(entry_1)?;
 
         writer.finish()?;
 
-        Ok(())
+        writer.into_inner()
+    }
+
+    fn create_numbered_file(
+        count: u64,
+        entries_per_chunk: u32,
+        compressed: bool,
+    ) -> Result<Vec<u8>, BdfError> {
+        let mut writer = numbered_writer(count, entries_per_chunk, compressed)?;
+        writer.finish()?;
+
+        writer.into_inner()
     }
 
-    fn new_reader(file_name: &str) -> Result<BDFReader, Error> {
-        let file = File::open(file_name)?;
+    fn numbered_writer(
+        count: u64,
+        entries_per_chunk: u32,
+        compressed: bool,
+    ) -> Result<BDFWriter<Vec<u8>>, BdfError> {
+        let mut writer = BDFWriter::new(Vec::new(), count, compressed);
+        add_numbered_entries(&mut writer, count, entries_per_chunk)?;
 
-        Ok(BDFReader::new(file))
+        Ok(writer)
     }
 
-    fn new_writer(file_name: &str, entries: u64, compress: bool) -> Result<BDFWriter, Error> {
-        let file = File::create(file_name)?;
+    fn write_numbered<W: Write>(
+        mut writer: BDFWriter<W>,
+        count: u64,
+        entries_per_chunk: u32,
+    ) -> Result<W, BdfError> {
+        add_numbered_entries(&mut writer, count, entries_per_chunk)?;
+        writer.finish()?;
+
+        writer.into_inner()
+    }
+
+    fn add_numbered_entries<W: Write>(
+        writer: &mut BDFWriter<W>,
+        count: u64,
+        entries_per_chunk: u32,
+    ) -> Result<(), BdfError> {
+        writer.set_entries_per_chunk(entries_per_chunk)?;
+        let foo = writer.add_lookup_entry(HashEntry::new(FOO.to_string(), 4))?;
+
+        for i in 0..count {
+            let mut entry = DataEntry::new(i.to_string());
+            entry.add_hash(foo, &(i as u32).to_be_bytes());
+            writer.add_data_entry(entry)?;
+        }
+
+        Ok(())
+    }
+
+    fn plain_string(entry: DataEntry) -> Result<String, BdfError> {
+        Ok(String::from_utf8(entry.plain)?)
+    }
 
-        Ok(BDFWriter::new(file, entries, compress))
+    fn new_reader(data: Vec<u8>) -> BDFReader<Cursor<Vec<u8>>> {
+        BDFReader::new(Cursor::new(data))
     }
 }
 
+pub mod builder;
 pub mod chunks;
+pub mod codec;
+pub mod error;
+pub mod executor;
 pub mod io;
+pub mod registry;
+pub mod salvage;
+mod sort;
+pub mod verify;
@@ -0,0 +1,65 @@
+use crate::chunks::{Chunk, ChunkId, GenericChunk};
+use crate::error::BdfError;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// Decodes the data of chunks of a custom type
+pub trait ChunkDecoder: Debug + Send + Sync {
+    /// Returns the type of the chunks the decoder reads
+    fn id(&self) -> ChunkId;
+
+    /// Decodes the data of a chunk that was written by [`Chunk::serialize_data`]
+    fn decode(&self, data: &[u8]) -> Result<Box<dyn Chunk>, BdfError>;
+}
+
+/// The custom chunk types known to a reader, keyed by their id.
+/// The chunk types of the format are always known and can't be registered.
+#[derive(Debug, Clone, Default)]
+pub struct ChunkRegistry {
+    decoders: HashMap<ChunkId, Arc<dyn ChunkDecoder>>,
+}
+
+impl ChunkRegistry {
+    /// Creates a registry without any custom chunk types
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a decoder to the registry, replacing any decoder for the same type.
+    /// Returns an error if the id isn't four ASCII letters or belongs to a chunk of the format.
+    pub fn register(&mut self, decoder: Arc<dyn ChunkDecoder>) -> Result<(), BdfError> {
+        validate_chunk_id(decoder.id())?;
+        self.decoders.insert(decoder.id(), decoder);
+
+        Ok(())
+    }
+
+    /// Returns the decoder for the given type
+    pub fn get(&self, id: ChunkId) -> Option<Arc<dyn ChunkDecoder>> {
+        self.decoders.get(&id).cloned()
+    }
+
+    /// Returns true if the type is defined by the format or has a registered decoder
+    pub fn is_known(&self, id: ChunkId) -> bool {
+        id.is_builtin() || self.decoders.contains_key(&id)
+    }
+
+    /// Decodes a chunk of a custom type.
+    /// Returns `None` if no decoder is registered for the type of the chunk.
+    pub fn decode(&self, chunk: &GenericChunk) -> Result<Option<Box<dyn Chunk>>, BdfError> {
+        self.decoders
+            .get(&chunk.id())
+            .map(|decoder| decoder.decode(&chunk.data))
+            .transpose()
+    }
+}
+
+/// Returns an error if the id can't be used for a custom chunk type
+pub(crate) fn validate_chunk_id(id: ChunkId) -> Result<(), BdfError> {
+    if !id.is_valid() || id.is_builtin() {
+        return Err(BdfError::InvalidChunkId(id.to_string()));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use crate::error::BdfError;
+use std::fmt::{self, Display, Formatter};
+
+/// The data that was recovered and lost by [`BDFReader::salvage`](crate::io::BDFReader::salvage)
+#[derive(Debug, Default)]
+pub struct SalvageReport {
+    /// The number of intact data chunks
+    pub chunks_recovered: u64,
+    /// The number of data entries returned, including those of a truncated chunk
+    pub entries_recovered: u64,
+    /// The parts of the file that were skipped in the order they were found
+    pub losses: Vec<Loss>,
+}
+
+/// A part of the file that couldn't be recovered
+#[derive(Debug)]
+pub enum Loss {
+    /// A chunk with a plausible header that is damaged. All bytes up to the
+    /// next intact chunk or the end of the file were skipped.
+    DamagedChunk {
+        offset: u64,
+        length: u64,
+        name: String,
+        error: BdfError,
+    },
+    /// Bytes that don't start with a plausible chunk header were skipped
+    /// up to the next intact chunk or the end of the file
+    Unreadable { offset: u64, length: u64 },
+    /// The file ends inside the chunk. The complete rows of a data chunk
+    /// were recovered, `missing` is the number of bytes the chunk lacks.
+    TruncatedChunk {
+        offset: u64,
+        name: String,
+        missing: u64,
+        entries_recovered: u32,
+    },
+}
+
+impl SalvageReport {
+    /// Returns true if nothing was lost
+    pub fn is_complete(&self) -> bool {
+        self.losses.is_empty()
+    }
+}
+
+impl Display for Loss {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Loss::DamagedChunk {
+                offset,
+                length,
+                name,
+                error,
+            } => write!(
+                f,
+                "skipped {} bytes of the damaged {} chunk at offset {}: {}",
+                length, name, offset, error
+            ),
+            Loss::Unreadable { offset, length } => {
+                write!(
+                    f,
+                    "skipped {} unreadable bytes at offset {}",
+                    length, offset
+                )
+            }
+            Loss::TruncatedChunk {
+                offset,
+                name,
+                missing,
+                entries_recovered,
+            } => write!(
+                f,
+                "the {} chunk at offset {} lacks {} bytes, recovered {} entries",
+                name, offset, missing, entries_recovered
+            ),
+        }
+    }
+}
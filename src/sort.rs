@@ -0,0 +1,176 @@
+use crate::chunks::{DataEntry, HashLookupTable};
+use crate::error::BdfError;
+use byteorder::{BigEndian, ByteOrder};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+
+/// Sorts data entries by the value of one hash function.
+/// If more entries are added than fit into the buffer, the sorted buffer
+/// is written to a temporary file and all files are merged at the end.
+#[derive(Debug)]
+pub(crate) struct ExternalSorter {
+    hash_id: u32,
+    hash_name: String,
+    buffer: Vec<DataEntry>,
+    buffer_size: usize,
+    runs: Vec<File>,
+}
+
+/// The source of one sorted sequence of entries that is merged
+#[derive(Debug)]
+enum SortedRun {
+    Memory(std::vec::IntoIter<DataEntry>),
+    File(BufReader<File>),
+}
+
+/// Iterator over the merged entries of all sorted runs
+#[derive(Debug)]
+pub(crate) struct SortedEntries {
+    hash_id: u32,
+    hash_name: String,
+    lookup_table: HashLookupTable,
+    runs: Vec<SortedRun>,
+    heads: Vec<Option<DataEntry>>,
+    heap: BinaryHeap<Reverse<(Vec<u8>, usize)>>,
+}
+
+impl ExternalSorter {
+    /// Creates a new sorter for the hash function with the given id and name
+    /// that keeps at most `buffer_size` entries in memory
+    pub fn new(hash_id: u32, hash_name: String, buffer_size: usize) -> Self {
+        Self {
+            hash_id,
+            hash_name,
+            buffer: Vec::new(),
+            buffer_size: buffer_size.max(1),
+            runs: Vec::new(),
+        }
+    }
+
+    /// Changes the maximum number of entries kept in memory
+    pub fn set_buffer_size(&mut self, buffer_size: usize) {
+        self.buffer_size = buffer_size.max(1);
+    }
+
+    /// Adds an entry to the sorter.
+    /// Returns an error if the entry doesn't have a value for the sort hash.
+    pub fn push(
+        &mut self,
+        entry: DataEntry,
+        lookup_table: &HashLookupTable,
+    ) -> Result<(), BdfError> {
+        if entry.hash(self.hash_id).is_none() {
+            return Err(BdfError::MissingHashValue(self.hash_name.clone()));
+        }
+        self.buffer.push(entry);
+        if self.buffer.len() >= self.buffer_size {
+            self.spill(lookup_table)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns all added entries sorted by the value of the sort hash
+    pub fn finish(mut self, lookup_table: &HashLookupTable) -> Result<SortedEntries, BdfError> {
+        let mut runs = Vec::new();
+        self.sort_buffer();
+        for mut file in self.runs {
+            file.seek(SeekFrom::Start(0))?;
+            runs.push(SortedRun::File(BufReader::new(file)));
+        }
+        runs.push(SortedRun::Memory(self.buffer.into_iter()));
+
+        let mut entries = SortedEntries {
+            hash_id: self.hash_id,
+            hash_name: self.hash_name,
+            lookup_table: lookup_table.clone(),
+            heads: Vec::new(),
+            heap: BinaryHeap::new(),
+            runs,
+        };
+        for run_number in 0..entries.runs.len() {
+            entries.heads.push(None);
+            entries.advance(run_number)?;
+        }
+
+        Ok(entries)
+    }
+
+    /// Writes the sorted buffer to a temporary file
+    fn spill(&mut self, lookup_table: &HashLookupTable) -> Result<(), BdfError> {
+        self.sort_buffer();
+        let mut writer = BufWriter::new(tempfile::tempfile()?);
+        for entry in self.buffer.drain(..) {
+            writer.write_all(&entry.serialize(lookup_table))?;
+        }
+        let file = writer
+            .into_inner()
+            .map_err(|err| BdfError::Io(err.into_error()))?;
+        self.runs.push(file);
+
+        Ok(())
+    }
+
+    fn sort_buffer(&mut self) {
+        let hash_id = self.hash_id;
+        self.buffer
+            .sort_by(|a, b| a.hash(hash_id).cmp(&b.hash(hash_id)));
+    }
+}
+
+impl SortedEntries {
+    /// Reads the next entry of the given run and adds it to the heap
+    fn advance(&mut self, run_number: usize) -> Result<(), BdfError> {
+        let next = match &mut self.runs[run_number] {
+            SortedRun::Memory(entries) => entries.next(),
+            SortedRun::File(reader) => read_run_entry(reader, &self.lookup_table)?,
+        };
+        if let Some(entry) = &next {
+            let key = entry
+                .hash(self.hash_id)
+                .map(<[u8]>::to_vec)
+                .ok_or_else(|| BdfError::MissingHashValue(self.hash_name.clone()))?;
+            self.heap.push(Reverse((key, run_number)));
+        }
+        self.heads[run_number] = next;
+
+        Ok(())
+    }
+}
+
+impl Iterator for SortedEntries {
+    type Item = Result<DataEntry, BdfError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((_, run_number)) = self.heap.pop()?;
+        let entry = self.heads[run_number].take()?;
+        if let Err(err) = self.advance(run_number) {
+            return Some(Err(err));
+        }
+
+        Some(Ok(entry))
+    }
+}
+
+/// Reads the next serialized entry of a temporary file
+fn read_run_entry(
+    reader: &mut BufReader<File>,
+    lookup_table: &HashLookupTable,
+) -> Result<Option<DataEntry>, BdfError> {
+    let mut length_raw = [0u8; 4];
+    if let Err(err) = reader.read_exact(&mut length_raw) {
+        return if err.kind() == ErrorKind::UnexpectedEof {
+            Ok(None)
+        } else {
+            Err(BdfError::Io(err))
+        };
+    }
+    let length = BigEndian::read_u32(&length_raw) as usize;
+    let mut data = vec![0u8; 4 + length];
+    data[..4].copy_from_slice(&length_raw);
+    reader.read_exact(&mut data[4..])?;
+
+    DataEntry::deserialize(&data, lookup_table).map(Some)
+}
@@ -0,0 +1,145 @@
+use crate::error::BdfError;
+use std::fmt::{self, Display, Formatter};
+
+/// The result of [`BDFReader::verify`](crate::io::BDFReader::verify)
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// The number of chunks whose crc sum was checked
+    pub chunks_checked: u64,
+    /// The number of `DTBL` chunks in the file
+    pub data_chunks: u64,
+    /// The number of data rows in all `DTBL` chunks
+    pub entries: u64,
+    /// Every problem found in the file in the order they were found
+    pub problems: Vec<Problem>,
+}
+
+/// A single problem found while verifying a file
+#[derive(Debug)]
+pub enum Problem {
+    /// The file doesn't start with a valid BDF header
+    InvalidHeader,
+    /// The header declares a format version this reader doesn't support
+    UnsupportedVersion(u8),
+    /// The file ended in the middle of the chunk starting at the offset
+    Truncated { offset: u64 },
+    /// A chunk of a different type was found where the `META` or `HTBL` chunk belongs
+    UnexpectedChunk {
+        chunk_index: u64,
+        expected: String,
+        found: String,
+    },
+    /// The crc sum stored in the chunk doesn't match its data
+    CrcMismatch {
+        chunk_index: u64,
+        name: String,
+        expected: u32,
+        actual: u32,
+    },
+    /// The chunk couldn't be decompressed or decoded
+    InvalidChunk {
+        chunk_index: u64,
+        name: String,
+        error: BdfError,
+    },
+    /// A data row references a hash function that isn't in the lookup table
+    UnknownHashId {
+        chunk_index: u64,
+        entry_number: u32,
+        hash_id: u32,
+    },
+    /// The hash values of a data row don't fill the row with the
+    /// output lengths stored in the lookup table
+    HashLengthMismatch {
+        chunk_index: u64,
+        entry_number: u32,
+        hash_id: u32,
+    },
+    /// The metadata declares UTF-8 plaintexts but the plaintext of a data row isn't valid UTF-8
+    InvalidPlaintext { chunk_index: u64, entry_number: u32 },
+    /// The chunk count in the metadata doesn't match the number of `DTBL` chunks
+    ChunkCountMismatch { expected: u32, actual: u64 },
+    /// The entry count in the metadata doesn't match the number of data rows
+    EntryCountMismatch { expected: u64, actual: u64 },
+}
+
+impl VerifyReport {
+    /// Returns true if no problems were found
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::InvalidHeader => write!(f, "invalid BDF header"),
+            Problem::UnsupportedVersion(version) => {
+                write!(f, "unsupported format version {}", version)
+            }
+            Problem::Truncated { offset } => {
+                write!(f, "the file ends inside the chunk at offset {}", offset)
+            }
+            Problem::UnexpectedChunk {
+                chunk_index,
+                expected,
+                found,
+            } => write!(
+                f,
+                "chunk {} should be a {} chunk but is {}",
+                chunk_index, expected, found
+            ),
+            Problem::CrcMismatch {
+                chunk_index,
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "crc mismatch in {} chunk {}: expected {:#010x}, got {:#010x}",
+                name, chunk_index, expected, actual
+            ),
+            Problem::InvalidChunk {
+                chunk_index,
+                name,
+                error,
+            } => write!(f, "invalid {} chunk {}: {}", name, chunk_index, error),
+            Problem::UnknownHashId {
+                chunk_index,
+                entry_number,
+                hash_id,
+            } => write!(
+                f,
+                "row {} of chunk {} references the unknown hash id {}",
+                entry_number, chunk_index, hash_id
+            ),
+            Problem::HashLengthMismatch {
+                chunk_index,
+                entry_number,
+                hash_id,
+            } => write!(
+                f,
+                "the hash values of row {} of chunk {} don't match the output length of hash id {}",
+                entry_number, chunk_index, hash_id
+            ),
+            Problem::InvalidPlaintext {
+                chunk_index,
+                entry_number,
+            } => write!(
+                f,
+                "the plaintext of row {} of chunk {} isn't valid utf-8",
+                entry_number, chunk_index
+            ),
+            Problem::ChunkCountMismatch { expected, actual } => write!(
+                f,
+                "the metadata declares {} data chunks but the file contains {}",
+                expected, actual
+            ),
+            Problem::EntryCountMismatch { expected, actual } => write!(
+                f,
+                "the metadata declares {} entries but the file contains {}",
+                expected, actual
+            ),
+        }
+    }
+}